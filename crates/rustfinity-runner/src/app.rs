@@ -0,0 +1,121 @@
+use clap::Parser;
+use dotenvy::dotenv;
+use serde_json::Value;
+use std::process::ExitCode;
+
+use crate::{
+    cache::ResultCache,
+    cli::{Cli, Commands, OutputFormat},
+    error::RunnerError,
+    events::{Event, Events},
+    job::{run_job, Job, JobConfig},
+    manifest::ManifestPolicy,
+    process::Limits,
+    sandbox::Sandbox,
+    server,
+};
+
+/// Runs the command line of the `rustfinity-runner` binary
+pub async fn main() -> ExitCode {
+    dotenv().ok();
+
+    let cli = Cli::parse();
+
+    let manifest_policy = match &cli.manifest_policy {
+        Some(path) => match ManifestPolicy::load(path) {
+            Ok(policy) => policy,
+            Err(e) => return fail(RunnerError::Internal(e), OutputFormat::Text),
+        },
+        None => ManifestPolicy::default(),
+    };
+    let mut limits = Limits::from(&cli.limits);
+    if let Err(e) = limits.sandbox.check() {
+        eprintln!(
+            "Warning: the namespaces sandbox isn't available, the submitted code runs without it: {:#}",
+            e
+        );
+        limits.sandbox = Sandbox::None;
+    }
+
+    let cache = match &cli.cache_dir {
+        Some(dir) => match ResultCache::new(dir, cli.cache_max_bytes) {
+            Ok(cache) => Some(cache),
+            Err(e) => return fail(RunnerError::Internal(e), OutputFormat::Text),
+        },
+        None => None,
+    };
+
+    let mut config = JobConfig {
+        limits,
+        manifest_policy,
+        heap_profiler: cli.heap_profiler,
+        events: Events::default(),
+        cache,
+    };
+
+    if let Commands::Serve {
+        listen,
+        workers,
+        queue_size,
+    } = cli.command
+    {
+        let workers = workers.unwrap_or_else(|| {
+            std::thread::available_parallelism().map_or(1, |workers| workers.get())
+        });
+
+        return match server::serve(&listen, workers, queue_size, config).await {
+            Ok(()) => ExitCode::SUCCESS,
+            Err(e) => fail(RunnerError::Internal(e), OutputFormat::Text),
+        };
+    }
+
+    // A job manifest has its own format, the command's is only used when the
+    // manifest can't be read
+    let command_format = cli.command.format();
+    let job = cli.command.into_job();
+    let format = job.as_ref().map_or(command_format, Job::format);
+    let job = match job {
+        Ok(job) => job,
+        Err(e) => return fail(RunnerError::InvalidJob(e), format),
+    };
+
+    let streaming = format == OutputFormat::Ndjson;
+    if streaming {
+        config.events = Events::new(|event| println!("{}", event.to_line()));
+    }
+
+    match run_job(job, config).await {
+        Ok(output) => {
+            let success = output.success();
+            match output.result {
+                result if streaming => println!(
+                    "{}",
+                    Event::Done {
+                        success,
+                        outcome: output.outcome,
+                        result: &result,
+                    }
+                    .to_line()
+                ),
+                Value::String(text) => println!("{}", text),
+                result => println!("{}", result),
+            }
+
+            ExitCode::from(output.outcome.exit_code())
+        }
+        Err(e) => fail(RunnerError::from(e), format),
+    }
+}
+
+/// Reports an error in the output format of the job, the JSON formats also
+/// print it to stdout for the calling service
+fn fail(error: RunnerError, format: OutputFormat) -> ExitCode {
+    match format {
+        OutputFormat::Text => {}
+        OutputFormat::Json => println!("{}", error.to_json()),
+        OutputFormat::Ndjson => println!("{}", Event::Error(&error.to_json()).to_line()),
+    }
+    eprintln!("{:#}", error);
+
+    ExitCode::from(error.exit_code())
+}
@@ -0,0 +1,135 @@
+use std::{
+    collections::HashMap,
+    io::Read,
+    path::{Component, Path, PathBuf},
+};
+
+use anyhow::Context;
+
+use crate::{files::Files, utils::read_input};
+
+/// The files of a crate sent as a tar archive, by their path in the crate
+#[derive(Debug)]
+pub struct Archive {
+    files: HashMap<PathBuf, String>,
+}
+
+impl Archive {
+    /// Reads the archive at `path`, `-` reads it from stdin
+    pub fn read(path: &str) -> anyhow::Result<Self> {
+        let bytes = read_input(path).context("Failed to read the archive")?;
+        Self::from_tar(bytes.as_slice())
+    }
+
+    fn from_tar(reader: impl Read) -> anyhow::Result<Self> {
+        let mut files = HashMap::new();
+
+        for entry in tar::Archive::new(reader).entries()? {
+            let mut entry = entry.context("Failed to read the archive")?;
+
+            if !entry.header().entry_type().is_file() {
+                continue;
+            }
+
+            // `tar -C crate .` prefixes every path with `./`
+            let path: PathBuf = entry
+                .path()?
+                .components()
+                .filter(|component| *component != Component::CurDir)
+                .collect();
+
+            let mut content = String::new();
+            entry
+                .read_to_string(&mut content)
+                .with_context(|| format!("{} is not valid UTF-8", path.display()))?;
+            files.insert(path, content);
+        }
+
+        Ok(Self { files }.without_root_directory())
+    }
+
+    /// Drops the directory the crate is in when the archive was made from
+    /// its parent, e.g. with `tar -cf crate.tar crate`. The crate is the
+    /// directory with the Cargo.toml
+    fn without_root_directory(self) -> Self {
+        if self.files.contains_key(Path::new("Cargo.toml")) {
+            return self;
+        }
+
+        let mut roots = self
+            .files
+            .keys()
+            .filter_map(|path| path.components().next());
+        let root = match roots.next() {
+            Some(root) if roots.all(|other| other == root) => PathBuf::from(root.as_os_str()),
+            _ => return self,
+        };
+
+        if !self.files.contains_key(&root.join("Cargo.toml")) {
+            return self;
+        }
+
+        let files = self
+            .files
+            .into_iter()
+            .map(|(path, content)| match path.strip_prefix(&root) {
+                Ok(relative) => (relative.to_path_buf(), content),
+                Err(_) => (path, content),
+            })
+            .collect();
+
+        Self { files }
+    }
+
+    /// Takes a file out of the archive
+    pub fn take(&mut self, path: &str) -> anyhow::Result<String> {
+        self.files
+            .remove(Path::new(path))
+            .with_context(|| format!("The archive has no {}", path))
+    }
+
+    /// The files left in the archive
+    pub fn into_files(self) -> Files {
+        self.files
+            .into_iter()
+            .map(|(path, content)| (path.to_string_lossy().to_string(), content))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tar(files: &[(&str, &str)]) -> Vec<u8> {
+        let mut builder = tar::Builder::new(Vec::new());
+
+        for (path, content) in files {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(content.len() as u64);
+            header.set_mode(0o644);
+            builder
+                .append_data(&mut header, path, content.as_bytes())
+                .unwrap();
+        }
+
+        builder.into_inner().unwrap()
+    }
+
+    #[test]
+    fn test_reads_crate_in_a_directory() {
+        let bytes = tar(&[
+            ("./hello/Cargo.toml", "[package]"),
+            ("./hello/src/lib.rs", "pub fn hello() {}"),
+        ]);
+
+        let mut archive = Archive::from_tar(bytes.as_slice()).unwrap();
+
+        assert_eq!(archive.take("Cargo.toml").unwrap(), "[package]");
+        assert!(archive.take("tests/tests.rs").is_err());
+        assert_eq!(
+            archive.into_files(),
+            Files::from([("src/lib.rs".to_string(), "pub fn hello() {}".to_string())])
+        );
+    }
+}
@@ -0,0 +1,462 @@
+use std::{fs, process::Command, time::Instant};
+
+use anyhow::{bail, Context};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    error::InvalidJob,
+    libtest::{parse_test_events, JSON_ARGS},
+    manifest::lib_name,
+    memory::{format_size, heaptrack, read_allocator_stats, HeapProfiler, ALLOCATOR_STATS_ENV},
+    process::{run, Limits, Phase},
+    utils::write_file,
+    verdict::{Benchmark, Comparison, FunctionBenchmark, HeapUsage, TestTiming, TimeStats},
+    workspace::Workspace,
+};
+
+/// Benchmark options of a `test` run
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[cfg_attr(feature = "cli", derive(clap::Args))]
+pub struct BenchmarkOptions {
+    /// Timed runs of the tests binary (default = 1)
+    #[cfg_attr(feature = "cli", clap(long = "n-tests", short))]
+    #[serde(default)]
+    pub n_tests: Option<usize>,
+
+    /// Runs of the tests binary before the timed ones (default = 1)
+    #[cfg_attr(feature = "cli", clap(long))]
+    #[serde(default)]
+    pub warmup: Option<usize>,
+
+    /// A call of one of the library's functions to time on its own, e.g.
+    /// `fibonacci(30)`, with the library's items in scope
+    #[cfg_attr(feature = "cli", clap(long))]
+    #[serde(default)]
+    pub benchmark_function: Option<String>,
+
+    /// Fails the comparison with the reference solution when the submission
+    /// is more than this many times slower, e.g. `2.0`
+    #[cfg_attr(feature = "cli", clap(long))]
+    #[serde(default)]
+    pub max_slowdown: Option<f64>,
+
+    /// Fails the comparison with the reference solution when the submission
+    /// uses more than this many times its memory
+    #[cfg_attr(feature = "cli", clap(long))]
+    #[serde(default)]
+    pub max_memory_ratio: Option<f64>,
+}
+
+impl BenchmarkOptions {
+    pub fn runs(&self) -> usize {
+        self.n_tests.unwrap_or(1).max(1)
+    }
+
+    pub fn warmup_runs(&self) -> usize {
+        self.warmup.unwrap_or(1)
+    }
+}
+
+/// Calls of the designated function timed, after the warm-up ones
+const FUNCTION_WARMUP_CALLS: usize = 10;
+const FUNCTION_CALLS: usize = 100;
+
+/// Example target the designated function is timed in, written once the
+/// tests pass so it isn't part of the tests' build
+pub const FUNCTION_EXAMPLE: &str = "__rustfinity_benchmark";
+
+/// Runs the tests binary one at a time with libtest's timings, after the
+/// warm-up runs, then the designated function and the heap profiler. Fails
+/// with [`LimitExceeded`](crate::process::LimitExceeded) if one of the runs
+/// hits a limit
+pub async fn benchmark(
+    workspace: &Workspace,
+    test_binary_path: &str,
+    compile_time_ms: f64,
+    options: &BenchmarkOptions,
+    heap_profiler: HeapProfiler,
+    limits: &Limits,
+) -> anyhow::Result<Benchmark> {
+    for _ in 0..options.warmup_runs() {
+        benchmark_run(workspace, test_binary_path, limits)?;
+    }
+
+    let runs = (0..options.runs())
+        .map(|_| benchmark_run(workspace, test_binary_path, limits))
+        .collect::<anyhow::Result<Vec<_>>>()?;
+
+    let run_times = runs.iter().map(|run| run.time_ms).collect::<Vec<_>>();
+    let peak_rss_bytes = runs
+        .iter()
+        .map(|run| run.peak_rss_bytes)
+        .max()
+        .unwrap_or_default();
+
+    let heap = match heap_profiler {
+        HeapProfiler::None => None,
+        HeapProfiler::Allocator => runs
+            .iter()
+            .filter_map(|run| run.heap)
+            .max_by_key(|heap| heap.peak_bytes),
+        HeapProfiler::Heaptrack => Some(heaptrack(workspace.path(), test_binary_path, limits)?),
+    };
+
+    let function = match &options.benchmark_function {
+        Some(call) => Some(benchmark_function(workspace, call, limits)?),
+        None => None,
+    };
+
+    Ok(Benchmark {
+        compile_time_ms,
+        run_time: TimeStats::from_samples(&run_times),
+        warmup_runs: options.warmup_runs(),
+        tests: test_timings(&runs),
+        function,
+        peak_rss_bytes,
+        heap,
+    })
+}
+
+struct BenchmarkRun {
+    time_ms: f64,
+    /// Run time of each test reported by libtest
+    tests: Vec<(String, f64)>,
+    peak_rss_bytes: u64,
+    /// Written by the counting allocator when the tests have it
+    heap: Option<HeapUsage>,
+}
+
+fn benchmark_run(
+    workspace: &Workspace,
+    test_binary_path: &str,
+    limits: &Limits,
+) -> anyhow::Result<BenchmarkRun> {
+    let stats_path = workspace.path().join(".rustfinity-alloc-stats");
+    let _ = fs::remove_file(&stats_path);
+
+    let start = Instant::now();
+
+    // One test at a time so their timings don't depend on the others
+    let output = run(
+        Command::new(test_binary_path)
+            .args(JSON_ARGS)
+            .arg("--test-threads=1")
+            .env("RUSTC_BOOTSTRAP", "1")
+            .env(ALLOCATOR_STATS_ENV, &stats_path)
+            .current_dir(workspace.path()),
+        Phase::Run,
+        limits,
+    )?
+    .check_limits()?;
+
+    let time_ms = start.elapsed().as_nanos() as f64 / 1_000_000.0;
+
+    Ok(BenchmarkRun {
+        time_ms,
+        tests: parse_test_events(&output.stdout)
+            .into_iter()
+            .filter_map(|test| Some((test.name, test.duration_ms?)))
+            .collect(),
+        peak_rss_bytes: output.peak_rss_bytes,
+        heap: read_allocator_stats(&stats_path),
+    })
+}
+
+/// The timings of each test across the runs, in the order they ran
+fn test_timings(runs: &[BenchmarkRun]) -> Vec<TestTiming> {
+    let mut names = Vec::<&str>::new();
+
+    for run in runs {
+        for (name, _) in &run.tests {
+            if !names.contains(&name.as_str()) {
+                names.push(name);
+            }
+        }
+    }
+
+    names
+        .into_iter()
+        .map(|name| {
+            let samples = runs
+                .iter()
+                .flat_map(|run| &run.tests)
+                .filter(|(test, _)| test == name)
+                .map(|(_, time_ms)| *time_ms)
+                .collect::<Vec<_>>();
+
+            TestTiming {
+                name: name.to_string(),
+                time: TimeStats::from_samples(&samples),
+            }
+        })
+        .collect()
+}
+
+/// Builds an example calling the function in a loop and times every call
+fn benchmark_function(
+    workspace: &Workspace,
+    call: &str,
+    limits: &Limits,
+) -> anyhow::Result<FunctionBenchmark> {
+    let cargo_toml = fs::read_to_string(workspace.path().join("Cargo.toml"))?;
+    let lib_name = lib_name(&cargo_toml).context(InvalidJob(
+        "The crate has no library to benchmark".to_string(),
+    ))?;
+
+    write_file(
+        &workspace
+            .path()
+            .join(format!("examples/{}.rs", FUNCTION_EXAMPLE)),
+        &function_example(&lib_name, call),
+    )?;
+
+    let build = run(
+        workspace
+            .cargo()
+            .args(["build", "--quiet", "--example", FUNCTION_EXAMPLE]),
+        Phase::Compile,
+        limits,
+    )?
+    .check_limits()?;
+
+    if !build.success() {
+        bail!(InvalidJob(format!(
+            "Failed to compile the benchmark of `{}`:\n{}",
+            call, build.stderr
+        )));
+    }
+
+    let output = run(
+        Command::new(
+            workspace
+                .path()
+                .join("target/debug/examples")
+                .join(FUNCTION_EXAMPLE),
+        )
+        .current_dir(workspace.path()),
+        Phase::Run,
+        limits,
+    )?
+    .check_limits()?;
+
+    let samples = output
+        .stdout
+        .split_whitespace()
+        .map(|nanos| nanos.parse::<f64>().map(|nanos| nanos / 1_000_000.0))
+        .collect::<Result<Vec<_>, _>>()
+        .ok()
+        .filter(|samples| output.success() && samples.len() == FUNCTION_CALLS)
+        .with_context(|| format!("The benchmark of `{}` failed:\n{}", call, output.stderr))?;
+
+    Ok(FunctionBenchmark {
+        call: call.to_string(),
+        time: TimeStats::from_samples(&samples),
+    })
+}
+
+fn function_example(lib_name: &str, call: &str) -> String {
+    format!(
+        r#"#![allow(warnings)]
+
+extern crate {lib_name};
+use {lib_name}::*;
+
+fn main() {{
+    for _ in 0..{FUNCTION_WARMUP_CALLS} {{
+        std::hint::black_box({call});
+    }}
+
+    let mut times = Vec::with_capacity({FUNCTION_CALLS});
+    for _ in 0..{FUNCTION_CALLS} {{
+        let start = std::time::Instant::now();
+        std::hint::black_box({call});
+        times.push(start.elapsed().as_nanos().to_string());
+    }}
+
+    println!("{{}}", times.join(" "));
+}}
+"#
+    )
+}
+
+/// Compares the submission's benchmark with the reference solution's and
+/// checks the thresholds
+pub fn compare(
+    submission: &Benchmark,
+    reference: Benchmark,
+    options: &BenchmarkOptions,
+) -> Comparison {
+    let speed_ratio = match (&submission.function, &reference.function) {
+        (Some(submission), Some(reference)) => {
+            ratio(submission.time.median_ms, reference.time.median_ms)
+        }
+        _ => ratio(submission.run_time.median_ms, reference.run_time.median_ms),
+    };
+    let memory_ratio = match (submission.heap, reference.heap) {
+        (Some(submission), Some(reference)) => {
+            ratio(submission.peak_bytes as f64, reference.peak_bytes as f64)
+        }
+        _ => ratio(
+            submission.peak_rss_bytes as f64,
+            reference.peak_rss_bytes as f64,
+        ),
+    };
+
+    let mut failures = Vec::new();
+
+    if let (Some(max), Some(ratio)) = (options.max_slowdown, speed_ratio) {
+        if ratio > max {
+            failures.push(format!(
+                "{}, at most {:.2}x slower is allowed",
+                describe_ratio(ratio, "slower", "faster"),
+                max
+            ));
+        }
+    }
+    if let (Some(max), Some(ratio)) = (options.max_memory_ratio, memory_ratio) {
+        if ratio > max {
+            failures.push(format!(
+                "{}, at most {:.2}x is allowed",
+                describe_ratio(ratio, "more memory", "less memory"),
+                max
+            ));
+        }
+    }
+
+    let has_thresholds = options.max_slowdown.is_some() || options.max_memory_ratio.is_some();
+
+    Comparison {
+        reference,
+        speed_ratio,
+        memory_ratio,
+        passed: has_thresholds.then_some(failures.is_empty()),
+        failures,
+    }
+}
+
+/// `None` when the reference measured nothing to compare with
+fn ratio(submission: f64, reference: f64) -> Option<f64> {
+    (reference > 0.0).then(|| submission / reference)
+}
+
+/// E.g. `2.30x slower than the reference` or `1.50x faster than the reference`
+fn describe_ratio(ratio: f64, more: &str, less: &str) -> String {
+    if (ratio - 1.0).abs() < 0.005 {
+        "about the same as the reference".to_string()
+    } else if ratio >= 1.0 {
+        format!("{:.2}x {} than the reference", ratio, more)
+    } else {
+        format!("{:.2}x {} than the reference", 1.0 / ratio, less)
+    }
+}
+
+/// Appends the comparison with the reference solution to the text output
+pub fn push_comparison(output: &mut String, comparison: &Comparison) {
+    output.push_str(&format!(
+        "\nReference time: {:.8}ms",
+        comparison.reference.run_time.median_ms
+    ));
+    if let Some(ratio) = comparison.speed_ratio {
+        output.push_str(&format!(
+            "\nSpeed: {}",
+            describe_ratio(ratio, "slower", "faster")
+        ));
+    }
+    if let Some(ratio) = comparison.memory_ratio {
+        output.push_str(&format!(
+            "\nMemory: {}",
+            describe_ratio(ratio, "more memory", "less memory")
+        ));
+    }
+    match comparison.passed {
+        Some(true) => output.push_str("\nPerformance: passed"),
+        Some(false) => output.push_str(&format!(
+            "\nPerformance: failed, {}",
+            comparison.failures.join(", ")
+        )),
+        None => {}
+    }
+}
+
+/// Appends the benchmark to the text output, the time is the median
+pub fn push_benchmark(output: &mut String, benchmark: &Benchmark) {
+    let run_time = &benchmark.run_time;
+
+    output.push_str("\n---\n");
+    output.push_str(&format!("Time: {:.8}ms\n", run_time.median_ms));
+    output.push_str(&format!(
+        "Runs: {} after {} warm-up, min {:.3}ms, p95 {:.3}ms, stddev {:.3}ms\n",
+        run_time.runs, benchmark.warmup_runs, run_time.min_ms, run_time.p95_ms, run_time.stddev_ms
+    ));
+    output.push_str(&format!(
+        "Compile time: {:.3}ms\n",
+        benchmark.compile_time_ms
+    ));
+    if let Some(function) = &benchmark.function {
+        output.push_str(&format!(
+            "{}: {:.6}ms (median of {} calls, p95 {:.6}ms)\n",
+            function.call, function.time.median_ms, function.time.runs, function.time.p95_ms
+        ));
+    }
+    output.push_str(&format!(
+        "Peak RSS: {}",
+        format_size(benchmark.peak_rss_bytes)
+    ));
+    if let Some(heap) = benchmark.heap {
+        output.push_str(&format!(
+            "\nPeak heap memory consumption: {}\nAllocations: {}",
+            format_size(heap.peak_bytes),
+            heap.allocations
+        ));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn benchmark(median_ms: f64, peak_bytes: u64) -> Benchmark {
+        Benchmark {
+            compile_time_ms: 0.0,
+            run_time: TimeStats::from_samples(&[median_ms]),
+            warmup_runs: 0,
+            tests: Vec::new(),
+            function: None,
+            peak_rss_bytes: 4096,
+            heap: Some(HeapUsage {
+                peak_bytes,
+                allocations: 1,
+                allocated_bytes: None,
+            }),
+        }
+    }
+
+    #[test]
+    fn test_compares_with_the_reference() {
+        let options = BenchmarkOptions {
+            max_slowdown: Some(2.0),
+            max_memory_ratio: Some(2.0),
+            ..Default::default()
+        };
+
+        let comparison = compare(&benchmark(4.6, 100), benchmark(2.0, 200), &options);
+        assert_eq!(comparison.speed_ratio, Some(2.3));
+        assert_eq!(comparison.memory_ratio, Some(0.5));
+        assert_eq!(comparison.passed, Some(false));
+        assert_eq!(
+            comparison.failures,
+            ["2.30x slower than the reference, at most 2.00x slower is allowed"]
+        );
+        assert_eq!(
+            describe_ratio(0.5, "more memory", "less memory"),
+            "2.00x less memory than the reference"
+        );
+
+        let comparison = compare(
+            &benchmark(2.0, 100),
+            benchmark(2.0, 100),
+            &Default::default(),
+        );
+        assert_eq!(comparison.passed, None);
+    }
+}
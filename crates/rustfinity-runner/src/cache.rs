@@ -0,0 +1,201 @@
+use std::{
+    fs::{self, File},
+    io::Write,
+    path::PathBuf,
+    process::Command,
+    time::SystemTime,
+};
+
+use anyhow::{bail, Context};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tempfile::NamedTempFile;
+
+use crate::workspace::project_path;
+
+/// Results of `test` jobs on disk, by a hash of everything that decides them:
+/// the normalised code and tests, the options of the job, the runner's
+/// settings and the toolchain. Identical submissions only run once, the
+/// least recently used results are evicted past `max_bytes`
+#[derive(Debug, Clone)]
+pub struct ResultCache {
+    dir: PathBuf,
+    max_bytes: u64,
+    /// `rustc -vV` of the toolchain the jobs are built with
+    toolchain: String,
+}
+
+/// A cached result with its whole key, a hash collision is a miss
+#[derive(Serialize, Deserialize)]
+struct Entry {
+    key: String,
+    result: Value,
+}
+
+impl ResultCache {
+    pub fn new(dir: impl Into<PathBuf>, max_bytes: u64) -> anyhow::Result<Self> {
+        let dir = dir.into();
+        fs::create_dir_all(&dir)
+            .with_context(|| format!("Failed to create the cache directory {}", dir.display()))?;
+
+        // The challenges directory can pin the toolchain
+        let rustc = Command::new("rustc")
+            .arg("-vV")
+            .current_dir(project_path())
+            .output()
+            .context("Failed to run rustc -vV")?;
+        if !rustc.status.success() {
+            bail!(
+                "Failed to get the toolchain version: {}",
+                String::from_utf8_lossy(&rustc.stderr)
+            );
+        }
+
+        Ok(Self {
+            dir,
+            max_bytes,
+            toolchain: String::from_utf8_lossy(&rustc.stdout).into_owned(),
+        })
+    }
+
+    /// The result of a job with this key, e.g. from [`job_key`]. Marks it as
+    /// recently used
+    pub fn get(&self, key: &str) -> Option<Value> {
+        let path = self.path(key);
+        let entry = serde_json::from_slice::<Entry>(&fs::read(&path).ok()?).ok()?;
+
+        if entry.key != self.full_key(key) {
+            return None;
+        }
+        if let Ok(file) = File::options().write(true).open(&path) {
+            file.set_modified(SystemTime::now()).ok();
+        }
+
+        Some(entry.result)
+    }
+
+    pub fn put(&self, key: &str, result: &Value) -> anyhow::Result<()> {
+        let entry = Entry {
+            key: self.full_key(key),
+            result: result.clone(),
+        };
+
+        // Written whole then renamed, concurrent jobs never read half of it
+        let mut file = NamedTempFile::new_in(&self.dir)?;
+        file.write_all(&serde_json::to_vec(&entry)?)?;
+        file.persist(self.path(key))?;
+
+        self.evict()
+    }
+
+    /// Removes the least recently used results until the cache fits in
+    /// `max_bytes`
+    fn evict(&self) -> anyhow::Result<()> {
+        let mut entries = Vec::new();
+        for entry in fs::read_dir(&self.dir)? {
+            let entry = entry?;
+            let Ok(metadata) = entry.metadata() else {
+                // Evicted by a concurrent job
+                continue;
+            };
+            if entry.path().extension().is_some_and(|ext| ext == "json") {
+                entries.push((metadata.modified()?, metadata.len(), entry.path()));
+            }
+        }
+
+        let mut total = entries.iter().map(|(_, len, _)| len).sum::<u64>();
+        entries.sort();
+
+        for (_, len, path) in entries {
+            if total <= self.max_bytes {
+                break;
+            }
+            fs::remove_file(path).ok();
+            total -= len;
+        }
+
+        Ok(())
+    }
+
+    fn full_key(&self, key: &str) -> String {
+        format!("{}\n{}\n{}", env!("CARGO_PKG_VERSION"), self.toolchain, key)
+    }
+
+    fn path(&self, key: &str) -> PathBuf {
+        self.dir.join(format!(
+            "{:032x}.json",
+            fnv1a(self.full_key(key).as_bytes())
+        ))
+    }
+}
+
+/// The key of a job from its parts, the sources are normalised so their
+/// line endings and trailing whitespace don't matter
+pub fn job_key<'a>(sources: impl IntoIterator<Item = &'a str>, options: &Value) -> String {
+    let sources = sources.into_iter().map(normalize).collect::<Vec<_>>();
+    serde_json::json!({ "sources": sources, "options": options }).to_string()
+}
+
+fn normalize(source: &str) -> String {
+    source
+        .lines()
+        .map(str::trim_end)
+        .collect::<Vec<_>>()
+        .join("\n")
+        .trim_end()
+        .to_string()
+}
+
+/// 128-bit FNV-1a, stable across Rust versions unlike the std hashers
+fn fnv1a(bytes: &[u8]) -> u128 {
+    const OFFSET_BASIS: u128 = 0x6c62272e07bb014262b821756295c58d;
+    const PRIME: u128 = 0x0000000001000000000000000000013b;
+
+    bytes.iter().fold(OFFSET_BASIS, |hash, byte| {
+        (hash ^ u128::from(*byte)).wrapping_mul(PRIME)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::TempDir;
+
+    use super::*;
+
+    #[test]
+    fn test_caches_and_evicts_results() {
+        let dir = TempDir::new().unwrap();
+        let cache = ResultCache {
+            dir: dir.path().to_path_buf(),
+            max_bytes: 200,
+            toolchain: "rustc 1.90.0".to_string(),
+        };
+
+        let options = serde_json::json!({ "format": "json" });
+        let key = job_key(["pub fn f() {}\r\n"], &options);
+        assert_eq!(key, job_key(["pub fn f() {}  \n\n"], &options));
+
+        assert_eq!(cache.get(&key), None);
+        cache
+            .put(&key, &serde_json::json!({ "success": true }))
+            .unwrap();
+        assert_eq!(
+            cache.get(&key),
+            Some(serde_json::json!({ "success": true }))
+        );
+
+        // Both entries don't fit, the least recently used one is evicted
+        File::options()
+            .write(true)
+            .open(cache.path(&key))
+            .unwrap()
+            .set_modified(SystemTime::UNIX_EPOCH)
+            .unwrap();
+        let other = job_key(["pub fn g() {}"], &options);
+        cache
+            .put(&other, &serde_json::json!({ "success": false }))
+            .unwrap();
+        assert_eq!(cache.get(&key), None);
+        assert!(cache.get(&other).is_some());
+    }
+}
@@ -0,0 +1,196 @@
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use serde::Deserialize;
+
+use crate::diagnostics::{Diagnostic, RustcDiagnostic};
+use crate::events::Events;
+use crate::process::{run_streaming, LimitExceeded, Limits, Phase};
+use crate::utils::{push_limit_exceeded, without_lock_lines};
+
+#[derive(Debug, Deserialize)]
+struct Message {
+    reason: String,
+    profile: Option<Profile>,
+    target: Option<Target>,
+    executable: Option<String>,
+    success: Option<bool>,
+    message: Option<RustcDiagnostic>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Profile {
+    test: bool,
+}
+
+#[derive(Debug, Deserialize)]
+struct Target {
+    kind: Vec<String>,
+    name: String,
+    src_path: String,
+}
+
+/// A test binary built by `cargo test --no-run`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TestExecutable {
+    /// The kinds of the target, `test` for the integration tests
+    pub kind: Vec<String>,
+    pub name: String,
+    pub src_path: String,
+    pub path: String,
+}
+
+/// What we need from the `--message-format=json` output of a cargo build
+#[derive(Debug, Default, PartialEq)]
+pub struct BuildMessages {
+    pub success: bool,
+    pub test_executables: Vec<TestExecutable>,
+    pub diagnostics: Vec<Diagnostic>,
+}
+
+/// A cargo build run with `--message-format=json`
+#[derive(Debug)]
+pub struct Build {
+    pub messages: BuildMessages,
+    /// Cargo's status lines together with the rendered diagnostics
+    pub output: String,
+    pub limit_exceeded: Option<LimitExceeded>,
+}
+
+/// Runs the cargo command with `--message-format=json` and the compile
+/// limits, the diagnostic spans are mapped onto the submitted `files`
+pub fn build(cargo: &mut Command, files: &[&str], limits: &Limits) -> anyhow::Result<Build> {
+    build_with_events(cargo, files, limits, &Events::default())
+}
+
+/// Same as [`build`], emitting an event for each crate cargo compiles
+pub fn build_with_events(
+    cargo: &mut Command,
+    files: &[&str],
+    limits: &Limits,
+    events: &Events,
+) -> anyhow::Result<Build> {
+    let root = cargo
+        .get_current_dir()
+        .map_or_else(PathBuf::new, Path::to_path_buf);
+    let events = events.clone();
+    let output = run_streaming(
+        cargo.arg("--message-format=json"),
+        Phase::Compile,
+        limits,
+        move |line| events.cargo_line(line),
+    )?;
+
+    let messages = parse_build_messages(&output.stdout, &root, files);
+    let mut compile_output = compile_output(&without_lock_lines(&output.stderr), &messages);
+
+    if let Some(limit_exceeded) = output.limit_exceeded {
+        push_limit_exceeded(&mut compile_output, &limit_exceeded);
+    }
+
+    Ok(Build {
+        messages,
+        output: compile_output,
+        limit_exceeded: output.limit_exceeded,
+    })
+}
+
+/// Puts the rendered diagnostics between cargo's `Compiling` lines and its
+/// final `Finished` or error line, the way cargo prints them without
+/// `--message-format=json`
+fn compile_output(stderr: &str, messages: &BuildMessages) -> String {
+    let (last, first): (Vec<&str>, Vec<&str>) = stderr
+        .lines()
+        .partition(|line| line.starts_with("error") || line.trim_start().starts_with("Finished"));
+
+    let mut output = String::new();
+
+    for line in first {
+        output.push_str(line);
+        output.push('\n');
+    }
+    for diagnostic in &messages.diagnostics {
+        output.push_str(&diagnostic.rendered);
+    }
+    for line in last {
+        output.push_str(line);
+        output.push('\n');
+    }
+
+    output
+}
+
+/// The test binaries and the diagnostics of a build of the crate at `root`
+pub fn parse_build_messages(stdout: &str, root: &Path, files: &[&str]) -> BuildMessages {
+    let mut messages = BuildMessages::default();
+
+    for message in stdout
+        .lines()
+        .filter_map(|line| serde_json::from_str::<Message>(line).ok())
+    {
+        match message.reason.as_str() {
+            "compiler-artifact" => {
+                let is_test = message.profile.is_some_and(|profile| profile.test);
+
+                if let (true, Some(target), Some(path)) =
+                    (is_test, message.target, message.executable)
+                {
+                    messages.test_executables.push(TestExecutable {
+                        kind: target.kind,
+                        name: target.name,
+                        src_path: target.src_path,
+                        path,
+                    });
+                }
+            }
+            "compiler-message" => {
+                let diagnostic = message
+                    .message
+                    .and_then(|diagnostic| diagnostic.into_diagnostic(root, files));
+
+                // Libraries are compiled twice by `cargo test`, once as a
+                // dependency of the tests and once with the unit tests
+                if let Some(diagnostic) = diagnostic {
+                    if !messages.diagnostics.contains(&diagnostic) {
+                        messages.diagnostics.push(diagnostic);
+                    }
+                }
+            }
+            "build-finished" => messages.success = message.success.unwrap_or(false),
+            _ => {}
+        }
+    }
+
+    messages
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parses_build_messages() {
+        let stdout = r#"{"reason":"compiler-artifact","package_id":"path+file:///app/challenges/playground#0.1.0","manifest_path":"/app/challenges/playground/Cargo.toml","target":{"kind":["lib"],"crate_types":["lib"],"name":"playground","src_path":"/app/challenges/playground/src/lib.rs","edition":"2021","doc":true,"doctest":true,"test":true},"profile":{"opt_level":"0","debuginfo":2,"debug_assertions":true,"overflow_checks":true,"test":false},"features":[],"filenames":["/app/target/debug/deps/libplayground-1.rlib"],"executable":null,"fresh":false}
+{"reason":"compiler-artifact","package_id":"path+file:///app/challenges/playground#0.1.0","manifest_path":"/app/challenges/playground/Cargo.toml","target":{"kind":["test"],"crate_types":["bin"],"name":"tests","src_path":"/app/challenges/playground/tests/tests.rs","edition":"2021","doc":false,"doctest":false,"test":true},"profile":{"opt_level":"0","debuginfo":2,"debug_assertions":true,"overflow_checks":true,"test":true},"features":[],"filenames":["/app/target/debug/deps/tests-2"],"executable":"/app/target/debug/deps/tests-2","fresh":false}
+{"reason":"build-finished","success":true}
+"#;
+
+        assert_eq!(
+            parse_build_messages(
+                stdout,
+                Path::new("/app/challenges/playground"),
+                &["src/lib.rs", "tests/tests.rs"]
+            ),
+            BuildMessages {
+                success: true,
+                test_executables: vec![TestExecutable {
+                    kind: vec!["test".to_string()],
+                    name: "tests".to_string(),
+                    src_path: "/app/challenges/playground/tests/tests.rs".to_string(),
+                    path: "/app/target/debug/deps/tests-2".to_string(),
+                }],
+                diagnostics: Vec::new(),
+            }
+        );
+    }
+}
@@ -1,10 +1,136 @@
-use clap::{Parser, Subcommand};
+use std::{collections::BTreeMap, time::Duration};
+
+use anyhow::{bail, Context};
+use clap::{builder::RangedU64ValueParser, Args, Parser, Subcommand, ValueEnum};
+use serde::Deserialize;
+
+use crate::{
+    archive::Archive,
+    benchmark::BenchmarkOptions,
+    commands::playground::Mode,
+    edition::Edition,
+    files::Files,
+    job::Job,
+    memory::HeapProfiler,
+    process::Limits,
+    rlimit::ResourceLimits,
+    sandbox::Sandbox,
+    utils::{from_base64, read_input, to_utf8},
+};
 
 #[derive(Debug, Parser)]
 #[clap(about = "CLI for rust code runner", long_about = None)]
 pub struct Cli {
     #[clap(subcommand)]
     pub command: Commands,
+
+    #[clap(flatten)]
+    pub limits: LimitArgs,
+
+    /// TOML file with the crates, editions and crate types a submitted
+    /// Cargo.toml can use, the default allows syn, quote, tempfile and syntest
+    #[clap(long, global = true, env = "RUNNER_MANIFEST_POLICY")]
+    pub manifest_policy: Option<String>,
+
+    /// How the benchmarks of `test` measure the heap usage, the peak RSS is
+    /// always measured
+    #[clap(long, global = true, env = "RUNNER_HEAP_PROFILER", value_enum, default_value_t = HeapProfiler::Allocator)]
+    pub heap_profiler: HeapProfiler,
+
+    /// Directory of the result cache, the results of `test` jobs are cached
+    /// by their code, tests and settings. Not cached without it
+    #[clap(long, global = true, env = "RUNNER_CACHE_DIR")]
+    pub cache_dir: Option<String>,
+
+    /// Bytes the result cache may use, the least recently used verdicts are
+    /// evicted past it
+    #[clap(long, global = true, env = "RUNNER_CACHE_MAX_BYTES", default_value_t = 256 * 1024 * 1024)]
+    pub cache_max_bytes: u64,
+}
+
+#[derive(Debug, Args)]
+pub struct LimitArgs {
+    /// Seconds cargo may spend compiling before it's killed
+    #[clap(
+        long,
+        global = true,
+        env = "RUNNER_COMPILE_TIMEOUT",
+        default_value_t = 60
+    )]
+    pub compile_timeout: u64,
+
+    /// Seconds the tests or the program may run before they're killed
+    #[clap(long, global = true, env = "RUNNER_RUN_TIMEOUT", default_value_t = 10)]
+    pub run_timeout: u64,
+
+    /// Bytes of output captured from a command before it's killed
+    #[clap(long, global = true, env = "RUNNER_MAX_OUTPUT_BYTES", default_value_t = 1024 * 1024)]
+    pub max_output_bytes: usize,
+
+    /// CPU seconds the tests or the program may use, 0 for no limit
+    #[clap(
+        long,
+        global = true,
+        env = "RUNNER_MAX_CPU_SECONDS",
+        default_value_t = 10
+    )]
+    pub max_cpu_seconds: u64,
+
+    /// Bytes of address space the tests or the program may map, 0 for no limit
+    #[clap(long, global = true, env = "RUNNER_MAX_MEMORY_BYTES", default_value_t = 2 * 1024 * 1024 * 1024)]
+    pub max_memory_bytes: u64,
+
+    /// Size of the files the tests or the program may write, 0 for no limit
+    #[clap(long, global = true, env = "RUNNER_MAX_FILE_SIZE_BYTES", default_value_t = 64 * 1024 * 1024)]
+    pub max_file_size_bytes: u64,
+
+    /// Processes and threads of the runner's user the tests or the program
+    /// may create, 0 for no limit
+    #[clap(
+        long,
+        global = true,
+        env = "RUNNER_MAX_PROCESSES",
+        default_value_t = 1024
+    )]
+    pub max_processes: u64,
+
+    /// Files the tests or the program may have open, 0 for no limit
+    #[clap(
+        long,
+        global = true,
+        env = "RUNNER_MAX_OPEN_FILES",
+        default_value_t = 1024
+    )]
+    pub max_open_files: u64,
+
+    /// Isolation of the tests and the program, `namespaces` falls back to
+    /// `none` with a warning when the host doesn't support it
+    #[clap(long, global = true, env = "RUNNER_SANDBOX", value_enum, default_value_t = Sandbox::None)]
+    pub sandbox: Sandbox,
+}
+
+impl From<&LimitArgs> for Limits {
+    fn from(args: &LimitArgs) -> Self {
+        Self {
+            compile_timeout: Duration::from_secs(args.compile_timeout),
+            run_timeout: Duration::from_secs(args.run_timeout),
+            max_output_bytes: args.max_output_bytes,
+            resources: ResourceLimits {
+                cpu_seconds: limit(args.max_cpu_seconds),
+                memory_bytes: limit(args.max_memory_bytes),
+                file_size_bytes: limit(args.max_file_size_bytes),
+                processes: limit(args.max_processes),
+                open_files: limit(args.max_open_files),
+            },
+            sandbox: args.sandbox,
+            ..Self::default()
+        }
+    }
+}
+
+/// `0` turns a resource limit off
+fn limit(value: u64) -> Option<u64> {
+    (value > 0).then_some(value)
 }
 
 #[derive(Debug, Subcommand)]
@@ -12,40 +138,503 @@ pub enum Commands {
     #[clap(about = "Run the code based on the code, tests, and cargo toml file provided")]
     Test {
         /// Code base64 encoded
-        #[clap(long)]
-        code: String,
+        #[clap(long, required_unless_present = "archive")]
+        code: Option<String>,
 
         /// Tests base64 encoded
+        #[clap(long, required_unless_present = "archive")]
+        tests: Option<String>,
+
+        /// Cargo toml base64 encoded
+        #[clap(long, required_unless_present = "archive")]
+        cargo_toml: Option<String>,
+
+        /// Hidden tests base64 encoded, their results only have the test's
+        /// name and status
+        #[clap(long)]
+        hidden_tests: Option<String>,
+
+        /// Scoring manifest base64 encoded, the TOML with the weights and
+        /// concept groups of the tests
+        #[clap(long)]
+        scoring: Option<String>,
+
+        /// Tar archive of the crate with `src/lib.rs`, `tests/tests.rs` and
+        /// `Cargo.toml` instead of the base64 arguments, the hidden tests in
+        /// `tests/hidden.rs` and the scoring manifest in `tests/scoring.toml`.
+        /// `-` reads it from stdin
+        #[clap(long, conflicts_with_all = ["code", "tests", "cargo_toml", "hidden_tests", "scoring"])]
+        archive: Option<String>,
+
+        /// Extra file of the crate, `<path>=<base64>` with the path relative
+        /// to the crate root, e.g. `src/parser.rs`. Can be repeated
+        #[clap(long)]
+        file: Vec<String>,
+
+        #[clap(flatten)]
+        benchmark: BenchmarkOptions,
+
+        /// Reference solution's `src/lib.rs` base64 encoded, benchmarked
+        /// after the submission to compare them
+        #[clap(long)]
+        reference: Option<String>,
+
+        /// Keeps cargo's build output, without it the output only has what
+        /// the learner needs
+        #[clap(long)]
+        verbose: bool,
+
+        /// Runs the tests even when the result cache has their verdict
         #[clap(long)]
-        tests: String,
+        no_cache: bool,
+
+        /// Output format, `json` returns a structured verdict with the
+        /// compiler diagnostics
+        #[clap(long, value_enum, default_value_t = OutputFormat::Text)]
+        format: OutputFormat,
+    },
+
+    #[clap(about = "Review the code of a challenge with clippy's lints")]
+    Lint {
+        /// Code base64 encoded
+        #[clap(long, required_unless_present = "archive")]
+        code: Option<String>,
 
         /// Cargo toml base64 encoded
+        #[clap(long, required_unless_present = "archive")]
+        cargo_toml: Option<String>,
+
+        /// Tar archive of the crate with `src/lib.rs` and `Cargo.toml`
+        /// instead of the base64 arguments, `-` reads it from stdin
+        #[clap(long, conflicts_with_all = ["code", "cargo_toml"])]
+        archive: Option<String>,
+
+        /// Extra file of the crate, `<path>=<base64>` with the path relative
+        /// to the crate root, e.g. `src/parser.rs`. Can be repeated
+        #[clap(long)]
+        file: Vec<String>,
+
+        /// Lint or lint group that fails the review when it fires, e.g.
+        /// `clippy::perf`. Can be repeated
         #[clap(long)]
-        cargo_toml: String,
+        deny: Vec<String>,
 
-        #[clap(long = "n-tests", short)]
-        /// number of tests to take the minimum time of
-        n_tests: Option<usize>,
+        /// Output format, `json` returns the lints and the score
+        #[clap(long, value_enum, default_value_t = OutputFormat::Text)]
+        format: OutputFormat,
+    },
+
+    #[clap(about = "Format the code with rustfmt")]
+    Fmt {
+        /// Code base64 encoded
+        #[clap(long)]
+        code: String,
+
+        #[clap(long, value_enum, default_value_t = Edition::default())]
+        edition: Edition,
+
+        /// rustfmt option, `<key>=<value>`, e.g. `max_width=80`. Can be repeated
+        #[clap(long)]
+        config: Vec<String>,
+
+        /// Output format, `json` also returns the diff and whether the code
+        /// was already formatted
+        #[clap(long, value_enum, default_value_t = OutputFormat::Text)]
+        format: OutputFormat,
     },
 
     #[clap(about = "Run and test the code based on the challenge and code provided")]
     Playground {
         /// Code base64 encoded
+        #[clap(long, required_unless_present = "archive")]
+        code: Option<String>,
+
+        /// Tar archive of the crate with `src/main.rs` instead of `--code`,
+        /// `-` reads it from stdin
+        #[clap(long, conflicts_with = "code")]
+        archive: Option<String>,
+
+        /// Extra file of the crate, `<path>=<base64>` with the path relative
+        /// to the crate root, e.g. `src/parser.rs`. Can be repeated
         #[clap(long)]
-        code: String,
+        file: Vec<String>,
+
+        /// Input of the program base64 encoded
+        #[clap(long)]
+        stdin: Option<String>,
+
+        /// Environment variable of the program, `<name>=<value>`. Can be
+        /// repeated
+        #[clap(long)]
+        env: Vec<String>,
+
+        /// Runs the program, its tests, or shows the code the compiler
+        /// generates for it
+        #[clap(long, value_enum, default_value_t = Mode::Run)]
+        mode: Mode,
+
+        /// Builds with the release profile
+        #[clap(long)]
+        release: bool,
+
+        /// Edition of the code, the playground's by default
+        #[clap(long, value_enum)]
+        edition: Option<Edition>,
+
+        /// Keeps cargo's build output, without it the output only has what
+        /// the learner needs
+        #[clap(long)]
+        verbose: bool,
+
+        /// Output format, `json` also returns the compiler diagnostics, the
+        /// program's stdout and stderr separately and its exit code
+        #[clap(long, value_enum, default_value_t = OutputFormat::Text)]
+        format: OutputFormat,
+
+        /// Arguments of the program, after `--`
+        #[clap(last = true)]
+        args: Vec<String>,
     },
 
     #[clap(about = "Run rustlings exercise with tests (cargo test)")]
     RustlingsTest {
         /// Code base64 encoded
+        #[clap(long, required_unless_present = "archive")]
+        code: Option<String>,
+
+        /// Tar archive of the crate with `src/main.rs` instead of `--code`,
+        /// `-` reads it from stdin
+        #[clap(long, conflicts_with = "code")]
+        archive: Option<String>,
+
+        /// Extra file of the crate, `<path>=<base64>` with the path relative
+        /// to the crate root, e.g. `src/parser.rs`. Can be repeated
         #[clap(long)]
-        code: String,
+        file: Vec<String>,
+
+        /// Keeps cargo's build output, without it the output only has what
+        /// the learner needs
+        #[clap(long)]
+        verbose: bool,
+
+        /// Output format, `json` also returns the compiler diagnostics
+        #[clap(long, value_enum, default_value_t = OutputFormat::Text)]
+        format: OutputFormat,
     },
 
     #[clap(about = "Check rustlings exercise compilation only (cargo check)")]
     RustlingsCheck {
         /// Code base64 encoded
+        #[clap(long, required_unless_present = "archive")]
+        code: Option<String>,
+
+        /// Tar archive of the crate with `src/main.rs` instead of `--code`,
+        /// `-` reads it from stdin
+        #[clap(long, conflicts_with = "code")]
+        archive: Option<String>,
+
+        /// Extra file of the crate, `<path>=<base64>` with the path relative
+        /// to the crate root, e.g. `src/parser.rs`. Can be repeated
         #[clap(long)]
-        code: String,
+        file: Vec<String>,
+
+        /// Keeps cargo's build output, without it the output only has what
+        /// the learner needs
+        #[clap(long)]
+        verbose: bool,
+
+        /// Output format, `json` also returns the compiler diagnostics
+        #[clap(long, value_enum, default_value_t = OutputFormat::Text)]
+        format: OutputFormat,
     },
+
+    #[clap(about = "Run a job manifest, the JSON jobs the job server accepts")]
+    Run {
+        /// Path of the manifest, `-` reads it from stdin
+        #[clap(long, default_value = "-")]
+        job: String,
+    },
+
+    #[clap(about = "Serve jobs over a JSON API, running them on a pool of workers")]
+    Serve {
+        /// Address to listen on, `host:port` or `unix:<path>` for a Unix socket
+        #[clap(long, env = "RUNNER_LISTEN", default_value = "127.0.0.1:8080")]
+        listen: String,
+
+        /// Jobs run at the same time, defaults to the number of CPUs
+        #[clap(long, env = "RUNNER_WORKERS", value_parser = RangedU64ValueParser::<usize>::new().range(1..))]
+        workers: Option<usize>,
+
+        /// Jobs waiting for a worker before new ones are rejected
+        #[clap(long, env = "RUNNER_QUEUE_SIZE", default_value_t = 100)]
+        queue_size: usize,
+    },
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputFormat {
+    /// Raw cargo output followed by the benchmark results
+    #[default]
+    Text,
+    /// JSON with the compile status, diagnostics and results
+    Json,
+    /// Progress events as newline-delimited JSON, the last one has the JSON
+    /// result. Only `test` reports its progress
+    Ndjson,
+}
+
+impl Commands {
+    /// Output format of the command, the text format for `run` and `serve`
+    /// whose jobs have their own
+    pub fn format(&self) -> OutputFormat {
+        match self {
+            Commands::Test { format, .. }
+            | Commands::Lint { format, .. }
+            | Commands::Fmt { format, .. }
+            | Commands::Playground { format, .. }
+            | Commands::RustlingsTest { format, .. }
+            | Commands::RustlingsCheck { format, .. } => *format,
+            Commands::Run { .. } | Commands::Serve { .. } => OutputFormat::Text,
+        }
+    }
+
+    /// The job the command runs, `serve` doesn't run one
+    pub fn into_job(self) -> anyhow::Result<Job> {
+        let job = match self {
+            Commands::Test {
+                archive: Some(archive),
+                file,
+                benchmark,
+                reference,
+                verbose,
+                no_cache,
+                format,
+                ..
+            } => {
+                let mut archive = Archive::read(&archive)?;
+
+                Job::Test {
+                    code: archive.take("src/lib.rs")?,
+                    tests: archive.take("tests/tests.rs")?,
+                    cargo_toml: archive.take("Cargo.toml")?,
+                    hidden_tests: archive.take("tests/hidden.rs").ok(),
+                    scoring: archive.take("tests/scoring.toml").ok(),
+                    files: with_file_args(archive.into_files(), file)?,
+                    benchmark,
+                    reference: decode_optional_arg("reference", reference)?,
+                    verbose,
+                    no_cache,
+                    format,
+                }
+            }
+
+            Commands::Test {
+                code,
+                tests,
+                cargo_toml,
+                hidden_tests,
+                scoring,
+                file,
+                benchmark,
+                reference,
+                verbose,
+                no_cache,
+                format,
+                ..
+            } => Job::Test {
+                code: decode_arg("code", code)?,
+                tests: decode_arg("tests", tests)?,
+                cargo_toml: decode_arg("cargo-toml", cargo_toml)?,
+                hidden_tests: decode_optional_arg("hidden-tests", hidden_tests)?,
+                scoring: decode_optional_arg("scoring", scoring)?,
+                files: with_file_args(Files::new(), file)?,
+                benchmark,
+                reference: decode_optional_arg("reference", reference)?,
+                verbose,
+                no_cache,
+                format,
+            },
+
+            Commands::Lint {
+                archive: Some(archive),
+                file,
+                deny,
+                format,
+                ..
+            } => {
+                let mut archive = Archive::read(&archive)?;
+
+                Job::Lint {
+                    code: archive.take("src/lib.rs")?,
+                    cargo_toml: archive.take("Cargo.toml")?,
+                    files: with_file_args(archive.into_files(), file)?,
+                    deny,
+                    format,
+                }
+            }
+
+            Commands::Lint {
+                code,
+                cargo_toml,
+                file,
+                deny,
+                format,
+                ..
+            } => Job::Lint {
+                code: decode_arg("code", code)?,
+                cargo_toml: decode_arg("cargo-toml", cargo_toml)?,
+                files: with_file_args(Files::new(), file)?,
+                deny,
+                format,
+            },
+
+            Commands::Fmt {
+                code,
+                edition,
+                config,
+                format,
+            } => Job::Fmt {
+                code: decode_arg("code", Some(code))?,
+                edition,
+                config,
+                format,
+            },
+
+            Commands::Playground {
+                code,
+                archive,
+                file,
+                stdin,
+                env,
+                mode,
+                release,
+                edition,
+                verbose,
+                format,
+                args,
+            } => {
+                let (code, files) = main_code(code, archive, file)?;
+                let stdin = match stdin {
+                    Some(stdin) => {
+                        from_base64(&stdin).context("Failed to decode --stdin from base64")?
+                    }
+                    None => Vec::new(),
+                };
+
+                Job::Playground {
+                    code,
+                    files,
+                    stdin,
+                    args,
+                    env: env_args(env)?,
+                    mode,
+                    release,
+                    edition,
+                    verbose,
+                    format,
+                }
+            }
+
+            Commands::RustlingsTest {
+                code,
+                archive,
+                file,
+                verbose,
+                format,
+            } => {
+                let (code, files) = main_code(code, archive, file)?;
+                Job::RustlingsTest {
+                    code,
+                    files,
+                    verbose,
+                    format,
+                }
+            }
+
+            Commands::RustlingsCheck {
+                code,
+                archive,
+                file,
+                verbose,
+                format,
+            } => {
+                let (code, files) = main_code(code, archive, file)?;
+                Job::RustlingsCheck {
+                    code,
+                    files,
+                    verbose,
+                    format,
+                }
+            }
+
+            Commands::Run { job } => {
+                let manifest = read_input(&job).context("Failed to read the job manifest")?;
+                serde_json::from_slice(&manifest).context("Invalid job manifest")?
+            }
+
+            Commands::Serve { .. } => bail!("serve doesn't run a single job"),
+        };
+
+        Ok(job)
+    }
+}
+
+fn decode_arg(name: &str, base64: Option<String>) -> anyhow::Result<String> {
+    let base64 = base64.with_context(|| format!("--{} is required", name))?;
+    to_utf8(&base64).with_context(|| format!("Failed to decode --{} from base64", name))
+}
+
+fn decode_optional_arg(name: &str, base64: Option<String>) -> anyhow::Result<Option<String>> {
+    base64
+        .map(|base64| decode_arg(name, Some(base64)))
+        .transpose()
+}
+
+/// Parses the `--env <name>=<value>` arguments
+fn env_args(args: Vec<String>) -> anyhow::Result<BTreeMap<String, String>> {
+    args.into_iter()
+        .map(|arg| match arg.split_once('=') {
+            Some((name, value)) => Ok((name.to_string(), value.to_string())),
+            None => bail!("Invalid --env `{}`, expected <name>=<value>", arg),
+        })
+        .collect()
+}
+
+/// Adds the `--file <path>=<base64>` arguments to the files
+fn with_file_args(mut files: Files, args: Vec<String>) -> anyhow::Result<Files> {
+    for arg in args {
+        let (path, base64) = arg
+            .split_once('=')
+            .with_context(|| format!("Invalid --file `{}`, expected <path>=<base64>", arg))?;
+
+        files.insert(
+            path.to_string(),
+            decode_arg("file", Some(base64.to_string()))?,
+        );
+    }
+
+    Ok(files)
+}
+
+/// The code of `--code` or `src/main.rs` of the archive with the other files
+/// of the archive, the crates of these commands have a fixed Cargo.toml
+fn main_code(
+    code: Option<String>,
+    archive: Option<String>,
+    file_args: Vec<String>,
+) -> anyhow::Result<(String, Files)> {
+    let (code, files) = match archive {
+        Some(archive) => {
+            let mut archive = Archive::read(&archive)?;
+            let code = archive.take("src/main.rs")?;
+            let _ = archive.take("Cargo.toml");
+            let _ = archive.take("Cargo.lock");
+
+            (code, archive.into_files())
+        }
+        None => (decode_arg("code", code)?, Files::new()),
+    };
+
+    Ok((code, with_file_args(files, file_args)?))
 }
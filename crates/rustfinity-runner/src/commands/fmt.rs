@@ -0,0 +1,147 @@
+use std::process::Command;
+
+use anyhow::{bail, Context};
+use serde::Serialize;
+use tempfile::TempDir;
+
+use crate::{
+    diff::unified_diff,
+    edition::Edition,
+    error::{InvalidJob, Outcome},
+    process::{run_with_input, LimitExceeded, Limits, Phase},
+};
+
+pub struct FmtParams {
+    code: String,
+    edition: Edition,
+    config: Vec<String>,
+    limits: Limits,
+}
+
+impl FmtParams {
+    pub fn new(code: String) -> Self {
+        Self {
+            code,
+            edition: Edition::default(),
+            config: Vec::new(),
+            limits: Limits::default(),
+        }
+    }
+
+    pub fn with_edition(mut self, edition: Edition) -> Self {
+        self.edition = edition;
+        self
+    }
+
+    /// rustfmt options as `key=value`, e.g. `max_width=80`
+    pub fn with_config(mut self, config: Vec<String>) -> Self {
+        self.config = config;
+        self
+    }
+
+    pub fn with_limits(mut self, limits: Limits) -> Self {
+        self.limits = limits;
+        self
+    }
+}
+
+/// Result of a `fmt` run
+#[derive(Debug, Serialize)]
+pub struct FmtResult {
+    /// Whether rustfmt could format the code
+    pub success: bool,
+    /// The formatted code, or the code as it was when rustfmt failed
+    pub formatted: String,
+    /// Unified diff from the code to the formatted code
+    pub diff: String,
+    pub already_formatted: bool,
+    /// What rustfmt printed to stderr, the parse errors when it failed
+    pub errors: String,
+    pub limit_exceeded: Option<LimitExceeded>,
+}
+
+impl FmtResult {
+    /// Code rustfmt can't parse is a compile error
+    pub fn outcome(&self) -> Outcome {
+        match &self.limit_exceeded {
+            Some(limit_exceeded) => Outcome::from_limit(limit_exceeded),
+            None if !self.success => Outcome::CompileError,
+            None => Outcome::Passed,
+        }
+    }
+
+    /// The formatted code, or the errors when it couldn't be formatted
+    pub fn text(&self) -> String {
+        if self.success {
+            self.formatted.clone()
+        } else {
+            self.errors.clone()
+        }
+    }
+}
+
+/// Formats the code with the toolchain's rustfmt, the same one used when
+/// grading. The code is given on stdin so nothing is written
+pub async fn run_fmt(params: &FmtParams) -> anyhow::Result<FmtResult> {
+    let FmtParams {
+        code,
+        edition,
+        config,
+        limits,
+    } = params;
+
+    // rustfmt looks for a `rustfmt.toml` from the current directory up
+    let empty_dir = TempDir::new().context("Failed to create temp directory")?;
+
+    let mut rustfmt = Command::new("rustfmt");
+    rustfmt
+        .args(["--edition", edition.as_str()])
+        .current_dir(empty_dir.path());
+    if !config.is_empty() {
+        rustfmt.args(["--config", &config_arg(config)?]);
+    }
+
+    let output = run_with_input(&mut rustfmt, code.as_bytes(), Phase::Compile, limits)
+        .context("Failed to run rustfmt")?;
+
+    if !output.success() {
+        return Ok(FmtResult {
+            success: false,
+            formatted: code.clone(),
+            diff: String::new(),
+            already_formatted: false,
+            errors: output.stderr,
+            limit_exceeded: output.limit_exceeded,
+        });
+    }
+
+    Ok(FmtResult {
+        success: true,
+        diff: unified_diff(code, &output.stdout, "original", "formatted"),
+        already_formatted: *code == output.stdout,
+        formatted: output.stdout,
+        errors: output.stderr,
+        limit_exceeded: None,
+    })
+}
+
+/// Joins the options into rustfmt's `--config key=value,key=value`
+fn config_arg(config: &[String]) -> anyhow::Result<String> {
+    for option in config {
+        let valid = option.split_once('=').is_some_and(|(key, value)| {
+            !key.is_empty()
+                && key.chars().all(|c| c.is_ascii_lowercase() || c == '_')
+                && !value.is_empty()
+                && !value.contains([',', '='])
+        });
+
+        if !valid {
+            bail!(InvalidJob(format!(
+                "Invalid rustfmt option `{}`, expected <key>=<value>",
+                option
+            )));
+        }
+    }
+
+    Ok(config.join(","))
+}
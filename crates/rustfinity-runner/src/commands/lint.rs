@@ -0,0 +1,281 @@
+use anyhow::Context;
+use serde::Serialize;
+use toml::{Table, Value};
+
+use crate::{
+    cargo::{build, Build},
+    diagnostics::Diagnostic,
+    error::{InvalidJob, Outcome},
+    files::Files,
+    manifest::ManifestPolicy,
+    process::{LimitExceeded, Limits},
+    verdict::CompileStatus,
+    workspace::Workspace,
+};
+
+/// Lints turned on on top of clippy's default groups, the pedantic ones that
+/// point at idiomatic Rust rather than personal taste
+const CURATED_LINTS: [&str; 12] = [
+    "clippy::cloned_instead_of_copied",
+    "clippy::explicit_iter_loop",
+    "clippy::implicit_clone",
+    "clippy::inefficient_to_string",
+    "clippy::manual_let_else",
+    "clippy::manual_string_new",
+    "clippy::map_unwrap_or",
+    "clippy::needless_pass_by_value",
+    "clippy::redundant_closure_for_method_calls",
+    "clippy::semicolon_if_nothing_returned",
+    "clippy::uninlined_format_args",
+    "clippy::unnested_or_patterns",
+];
+
+/// Points taken off the score of 100 for each lint
+const WARNING_PENALTY: u32 = 5;
+const DENIED_PENALTY: u32 = 15;
+
+pub struct LintParams {
+    code: String,
+    cargo_toml: String,
+    files: Files,
+    deny: Vec<String>,
+    manifest_policy: ManifestPolicy,
+    limits: Limits,
+}
+
+impl LintParams {
+    pub fn new(code: String, cargo_toml: String) -> Self {
+        Self {
+            code,
+            cargo_toml,
+            files: Files::new(),
+            deny: Vec::new(),
+            manifest_policy: ManifestPolicy::default(),
+            limits: Limits::default(),
+        }
+    }
+
+    /// Extra files of the crate, e.g. modules of the library
+    pub fn with_files(mut self, files: Files) -> Self {
+        self.files = files;
+        self
+    }
+
+    /// Lints or lint groups that fail the review, e.g. `clippy::perf`
+    pub fn with_deny(mut self, deny: Vec<String>) -> Self {
+        self.deny = deny;
+        self
+    }
+
+    pub fn with_manifest_policy(mut self, manifest_policy: ManifestPolicy) -> Self {
+        self.manifest_policy = manifest_policy;
+        self
+    }
+
+    pub fn with_limits(mut self, limits: Limits) -> Self {
+        self.limits = limits;
+        self
+    }
+}
+
+/// Result of a `lint` run
+#[derive(Debug, Serialize)]
+pub struct LintReport {
+    /// Failed when the code has errors other than denied lints
+    pub compile: CompileStatus,
+    /// The lints that fired, denied ones have the `error` level
+    pub lints: Vec<Diagnostic>,
+    /// Compiler errors and warnings that aren't lints
+    pub diagnostics: Vec<Diagnostic>,
+    /// 100 without lints, lower the more lints fired
+    pub score: u32,
+    /// Whether the code compiles without denied lints
+    pub passed: bool,
+    /// Clippy's output
+    pub output: String,
+    pub limit_exceeded: Option<LimitExceeded>,
+}
+
+impl LintReport {
+    pub fn outcome(&self) -> Outcome {
+        match &self.limit_exceeded {
+            Some(limit_exceeded) => Outcome::from_limit(limit_exceeded),
+            None if self.compile == CompileStatus::Failed => Outcome::CompileError,
+            None if !self.passed => Outcome::Failed,
+            None => Outcome::Passed,
+        }
+    }
+
+    /// The clippy output followed by the score
+    pub fn text(&self) -> String {
+        let mut output = self.output.clone();
+
+        if self.compile == CompileStatus::Success {
+            let denied = self.lints.iter().filter(|lint| is_denied(lint)).count();
+
+            output.push_str("\n---\n");
+            output.push_str(&format!("Score: {}/100\n", self.score));
+            output.push_str(&format!(
+                "Lints: {} warnings, {} denied",
+                self.lints.len() - denied,
+                denied
+            ));
+        }
+
+        output
+    }
+}
+
+/// Runs `cargo clippy` on the library with the curated lints and the denied
+/// ones, fails with [`PolicyViolation`](crate::manifest::PolicyViolation) if
+/// the Cargo.toml isn't allowed
+pub async fn run_lint(params: &LintParams) -> anyhow::Result<LintReport> {
+    let LintParams {
+        code,
+        cargo_toml,
+        files,
+        deny,
+        manifest_policy,
+        limits,
+    } = params;
+
+    let cargo_toml = with_lints(&manifest_policy.apply(cargo_toml)?, deny)?;
+
+    let mut workspace = Workspace::new()?;
+    workspace.write("src/lib.rs", code)?;
+    workspace.write_files(files)?;
+    workspace.write_manifest(&cargo_toml)?;
+
+    let Build {
+        messages,
+        output,
+        limit_exceeded,
+    } = build(
+        workspace.cargo().args(["clippy", "--lib"]),
+        &workspace.files(),
+        limits,
+    )?;
+
+    let (lints, diagnostics): (Vec<_>, Vec<_>) =
+        messages.diagnostics.into_iter().partition(is_lint);
+
+    // Denied lints fail the build like errors do
+    let has_errors = diagnostics
+        .iter()
+        .any(|diagnostic| diagnostic.level == "error");
+    let compiles = !has_errors
+        && limit_exceeded.is_none()
+        && (messages.success || lints.iter().any(is_denied));
+
+    let penalty = lints
+        .iter()
+        .map(|lint| {
+            if is_denied(lint) {
+                DENIED_PENALTY
+            } else {
+                WARNING_PENALTY
+            }
+        })
+        .sum::<u32>();
+
+    if !compiles {
+        return Ok(LintReport {
+            compile: CompileStatus::Failed,
+            lints,
+            diagnostics,
+            score: 0,
+            passed: false,
+            output,
+            limit_exceeded,
+        });
+    }
+
+    Ok(LintReport {
+        compile: CompileStatus::Success,
+        passed: !lints.iter().any(is_denied),
+        score: 100u32.saturating_sub(penalty),
+        lints,
+        diagnostics,
+        output,
+        limit_exceeded,
+    })
+}
+
+/// Replaces the `[lints]` of the manifest with clippy's default groups, the
+/// curated lints and the denied ones. Cargo only passes them to the crate,
+/// the precompiled dependencies aren't rebuilt
+fn with_lints(cargo_toml: &str, deny: &[String]) -> anyhow::Result<String> {
+    let mut manifest = cargo_toml
+        .parse::<Table>()
+        .context(InvalidJob("Invalid Cargo.toml".to_string()))?;
+    let mut lints = Table::new();
+
+    let mut set = |lint: &str, level: &str, priority: i64| {
+        let (tool, name) = match lint.split_once("::") {
+            Some((tool, name)) => (tool, name),
+            None => ("rust", lint),
+        };
+
+        let mut config = Table::new();
+        config.insert("level".to_string(), Value::from(level));
+        config.insert("priority".to_string(), Value::from(priority));
+
+        lints
+            .entry(tool)
+            .or_insert_with(|| Value::Table(Table::new()))
+            .as_table_mut()
+            .expect("lint tools are tables")
+            .insert(name.to_string(), Value::Table(config));
+    };
+
+    // Groups first so the lints in them can be set on their own
+    set("clippy::all", "warn", -1);
+    for lint in CURATED_LINTS {
+        set(lint, "warn", 0);
+    }
+    for lint in deny {
+        set(lint, "deny", 1);
+    }
+
+    manifest.insert("lints".to_string(), Value::Table(lints));
+
+    Ok(manifest.to_string())
+}
+
+/// Lints have their name as the code, compiler errors an `E` code or none
+fn is_lint(diagnostic: &Diagnostic) -> bool {
+    diagnostic.code.as_deref().is_some_and(|code| {
+        code.chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_' || c == ':')
+    })
+}
+
+fn is_denied(lint: &Diagnostic) -> bool {
+    lint.level == "error"
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sets_the_lints_in_the_manifest() {
+        let manifest = with_lints(
+            "[package]\nname = \"lint\"\n\n[lints.rust]\nunused = \"allow\"\n",
+            &["clippy::perf".to_string(), "unused_mut".to_string()],
+        )
+        .unwrap()
+        .parse::<Table>()
+        .unwrap();
+
+        let lints = &manifest["lints"];
+        assert_eq!(lints["clippy"]["all"]["priority"].as_integer(), Some(-1));
+        assert_eq!(
+            lints["clippy"]["manual_let_else"]["level"].as_str(),
+            Some("warn")
+        );
+        assert_eq!(lints["clippy"]["perf"]["level"].as_str(), Some("deny"));
+        assert_eq!(lints["rust"]["unused_mut"]["level"].as_str(), Some("deny"));
+        assert!(lints["rust"].get("unused").is_none());
+    }
+}
@@ -1,3 +1,14 @@
+use crate::error::Outcome;
+
+pub mod fmt;
+pub mod lint;
 pub mod playground;
 pub mod run_tests;
 pub mod rustlings;
+
+/// The text output of a command, with how it ended
+#[derive(Debug)]
+pub struct TextOutput {
+    pub output: String,
+    pub outcome: Outcome,
+}
@@ -1,40 +1,426 @@
-use std::path::Path;
+use std::{
+    collections::BTreeMap,
+    fs,
+    os::unix::process::ExitStatusExt,
+    path::{Path, PathBuf},
+    process::{Command, ExitStatus},
+};
+
+use anyhow::{bail, Context};
+use serde::{Deserialize, Serialize};
 
 use crate::{
-    constants::PLAYGROUND_DIR,
-    utils::{run_command_and_merge_output, to_utf8, write_file},
+    cargo::{build, Build},
+    commands::TextOutput,
+    diagnostics::Diagnostic,
+    edition::Edition,
+    error::{InvalidJob, Outcome},
+    files::Files,
+    manifest::package_name,
+    process::{
+        run, run_merged_with_input, run_with_input, LimitExceeded, Limits, Phase, ProcessOutput,
+    },
+    sanitize::{strip_colors, Sanitizer},
+    utils::push_limit_exceeded,
+    verdict::CompileStatus,
+    workspace::Workspace,
 };
 
+/// What the playground does with the code once it compiles
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+#[serde(rename_all = "kebab-case")]
+pub enum Mode {
+    /// Runs the program
+    #[default]
+    Run,
+    /// Runs the tests of the program
+    Test,
+    /// Shows the generated assembly
+    Asm,
+    /// Shows the generated LLVM IR
+    LlvmIr,
+    /// Shows the MIR
+    Mir,
+    /// Shows the HIR
+    Hir,
+    /// Shows the code with its macros expanded
+    Expanded,
+}
+
+impl Mode {
+    /// The `rustc` arguments showing the code in this mode, with the file
+    /// they write it to. `-Zunpretty` prints to stdout
+    fn emission(&self) -> Option<(&'static [&'static str], Option<&'static str>)> {
+        match self {
+            Mode::Run | Mode::Test => None,
+            Mode::Asm => Some((
+                &["--emit", "asm=emitted.s", "-C", "codegen-units=1"],
+                Some("emitted.s"),
+            )),
+            Mode::LlvmIr => Some((
+                &["--emit", "llvm-ir=emitted.ll", "-C", "codegen-units=1"],
+                Some("emitted.ll"),
+            )),
+            Mode::Mir => Some((&["--emit", "mir=emitted.mir"], Some("emitted.mir"))),
+            Mode::Hir => Some((&["-Zunpretty=hir"], None)),
+            Mode::Expanded => Some((&["-Zunpretty=expanded"], None)),
+        }
+    }
+}
+
 pub struct PlaygroundParams {
-    code_base64: String,
+    code: String,
+    files: Files,
+    stdin: Vec<u8>,
+    args: Vec<String>,
+    env: BTreeMap<String, String>,
+    mode: Mode,
+    release: bool,
+    edition: Option<Edition>,
+    limits: Limits,
+    verbose: bool,
 }
 
 impl PlaygroundParams {
-    pub fn new(code_base64: String) -> Self {
-        Self { code_base64 }
+    pub fn new(code: String) -> Self {
+        Self {
+            code,
+            files: Files::new(),
+            stdin: Vec::new(),
+            args: Vec::new(),
+            env: BTreeMap::new(),
+            mode: Mode::default(),
+            release: false,
+            edition: None,
+            limits: Limits::default(),
+            verbose: false,
+        }
+    }
+
+    /// Written to the program's stdin, it reads end of file after it
+    pub fn with_stdin(mut self, stdin: Vec<u8>) -> Self {
+        self.stdin = stdin;
+        self
+    }
+
+    /// Arguments of the program, `std::env::args` after the program name.
+    /// In the test mode they go to the test harness, e.g. a test name filter
+    pub fn with_args(mut self, args: Vec<String>) -> Self {
+        self.args = args;
+        self
+    }
+
+    /// Environment variables set for the program
+    pub fn with_env(mut self, env: BTreeMap<String, String>) -> Self {
+        self.env = env;
+        self
+    }
+
+    pub fn with_mode(mut self, mode: Mode) -> Self {
+        self.mode = mode;
+        self
+    }
+
+    /// Builds with the release profile instead of the dev one
+    pub fn with_release(mut self, release: bool) -> Self {
+        self.release = release;
+        self
+    }
+
+    /// Edition of the code, the playground's when `None`
+    pub fn with_edition(mut self, edition: Option<Edition>) -> Self {
+        self.edition = edition;
+        self
+    }
+
+    /// Extra files of the crate, e.g. modules of the binary
+    pub fn with_files(mut self, files: Files) -> Self {
+        self.files = files;
+        self
+    }
+
+    pub fn with_limits(mut self, limits: Limits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    /// Keeps cargo's build output in the text output
+    pub fn with_verbose(mut self, verbose: bool) -> Self {
+        self.verbose = verbose;
+        self
+    }
+
+    /// A cargo command of the workspace with the profile
+    fn cargo(&self, workspace: &Workspace, args: &[&str]) -> Command {
+        let mut cargo = workspace.cargo();
+        cargo.args(args);
+        if self.release {
+            cargo.arg("--release");
+        }
+        cargo
+    }
+}
+
+pub async fn run_code_in_playground(params: &PlaygroundParams) -> anyhow::Result<TextOutput> {
+    let report = execute_code(params, true).await?;
+    let outcome = report.outcome();
+
+    // The build output already ends with its limit
+    let mut output = Sanitizer::new(&report.root, params.verbose).sanitize(&report.output);
+    if report.compile == CompileStatus::Failed {
+        return Ok(TextOutput { output, outcome });
+    }
+
+    match report.emitted {
+        Some(emitted) => output.push_str(&emitted),
+        None => output.push_str(&strip_colors(&report.stdout)),
+    }
+
+    match report.limit_exceeded {
+        Some(limit_exceeded) => push_limit_exceeded(&mut output, &limit_exceeded),
+        None => {
+            if let Some(status) = report.status {
+                push_exit_status(&mut output, &status);
+            }
+        }
+    }
+
+    Ok(TextOutput { output, outcome })
+}
+
+/// Result of a playground run, returned with `--format json`
+#[derive(Debug, Serialize)]
+pub struct PlaygroundReport {
+    pub compile: CompileStatus,
+    pub diagnostics: Vec<Diagnostic>,
+    /// Output of the cargo build
+    pub output: String,
+    /// Output of the program or its tests, empty if it didn't compile
+    pub stdout: String,
+    pub stderr: String,
+    /// `None` when the program didn't run or was killed by a signal
+    pub exit_code: Option<i32>,
+    /// The assembly, IR or expanded code in the modes showing it
+    pub emitted: Option<String>,
+    pub limit_exceeded: Option<LimitExceeded>,
+    #[serde(skip)]
+    status: Option<ExitStatus>,
+    /// Where the code was built, to sanitise the build output
+    #[serde(skip)]
+    root: PathBuf,
+}
+
+impl PlaygroundReport {
+    /// Failed when the program or its tests exited with an error
+    pub fn outcome(&self) -> Outcome {
+        match &self.limit_exceeded {
+            Some(limit_exceeded) => Outcome::from_limit(limit_exceeded),
+            None if self.compile == CompileStatus::Failed => Outcome::CompileError,
+            None if self.status.is_some_and(|status| !status.success()) => Outcome::Failed,
+            None => Outcome::Passed,
+        }
+    }
+}
+
+/// Same as [`run_code_in_playground`] but also returns the compiler
+/// diagnostics mapped onto `src/main.rs`, and the program's stdout and stderr
+/// separately
+pub async fn run_playground_report(params: &PlaygroundParams) -> anyhow::Result<PlaygroundReport> {
+    execute_code(params, false).await
+}
+
+/// Builds the code then runs it, runs its tests or shows it, each step with
+/// its own limits. `merged` puts the program's stderr in its stdout, in the
+/// order they were written
+async fn execute_code(params: &PlaygroundParams, merged: bool) -> anyhow::Result<PlaygroundReport> {
+    let workspace = write_code(&params.code, &params.files, params.edition)?;
+
+    let build_args: &[&str] = match params.mode {
+        Mode::Test => &["test", "--no-run"],
+        _ => &["build"],
+    };
+    let Build {
+        messages,
+        output,
+        limit_exceeded,
+    } = build(
+        &mut params.cargo(&workspace, build_args),
+        &workspace.files(),
+        &params.limits,
+    )?;
+
+    let mut report = PlaygroundReport {
+        compile: CompileStatus::Failed,
+        diagnostics: messages.diagnostics,
+        output,
+        stdout: String::new(),
+        stderr: String::new(),
+        exit_code: None,
+        emitted: None,
+        limit_exceeded,
+        status: None,
+        root: workspace.path().to_path_buf(),
+    };
+
+    if !messages.success || report.limit_exceeded.is_some() {
+        return Ok(report);
+    }
+    report.compile = CompileStatus::Success;
+
+    if let Some((rustc_args, file)) = params.mode.emission() {
+        let emitted = emit(&workspace, params, rustc_args, file)?;
+        report.emitted = Some(emitted.stdout);
+        report.limit_exceeded = emitted.limit_exceeded;
+
+        return Ok(report);
+    }
+
+    let programs = match params.mode {
+        Mode::Test => messages
+            .test_executables
+            .iter()
+            .map(|executable| Path::new(&executable.path).to_path_buf())
+            .collect(),
+        _ => vec![workspace
+            .path()
+            .join("target")
+            .join(if params.release { "release" } else { "debug" })
+            .join(binary_name(&workspace)?)],
+    };
+
+    for path in programs {
+        let mut program = program(&workspace, params, &path)?;
+        let output = if merged {
+            run_merged_with_input(&mut program, &params.stdin, Phase::Run, &params.limits)?
+        } else {
+            run_with_input(&mut program, &params.stdin, Phase::Run, &params.limits)?
+        };
+
+        report.stdout.push_str(&output.stdout);
+        report.stderr.push_str(&output.stderr);
+        report.exit_code = output.status.code();
+        report.status = Some(output.status);
+        report.limit_exceeded = output.limit_exceeded;
+
+        // Like `cargo test`, the tests stop at the first failing binary
+        if !output.success() || report.limit_exceeded.is_some() {
+            break;
+        }
     }
+
+    Ok(report)
 }
 
-pub async fn run_code_in_playground(params: &PlaygroundParams) -> anyhow::Result<String> {
-    let PlaygroundParams { code_base64 } = params;
+/// Compiles the binary again with the mode's `rustc` arguments, the output
+/// is what the compiler emitted. The compile limits apply, the output limit
+/// to the emitted file too
+fn emit(
+    workspace: &Workspace,
+    params: &PlaygroundParams,
+    rustc_args: &[&str],
+    file: Option<&str>,
+) -> anyhow::Result<ProcessOutput> {
+    let name = binary_name(workspace)?;
+
+    let mut rustc = params.cargo(workspace, &["rustc", "--quiet", "--bin", &name]);
+    rustc.arg("--").args(rustc_args);
+
+    // `-Zunpretty` is only accepted by nightly compilers otherwise. It's left
+    // unset for the other modes, dependencies reading it in their build
+    // scripts are rebuilt when it changes
+    if file.is_none() {
+        rustc.env("RUSTC_BOOTSTRAP", "1");
+    }
+
+    let mut output = run(&mut rustc, Phase::Compile, &params.limits)?;
+
+    // A limit is reported like the build's, with what was emitted before it
+    if output.limit_exceeded.is_some() {
+        return Ok(output);
+    }
+    if !output.status.success() {
+        bail!("Failed to emit the code: {}", output.stderr);
+    }
 
-    let mut output = String::new();
+    if let Some(file) = file {
+        let emitted =
+            fs::read(workspace.path().join(file)).context("Failed to read the emitted code")?;
+        let max_bytes = params.limits.max_output_bytes;
 
-    let tests_output = execute_code(&code_base64).await?;
-    output.push_str(&tests_output);
+        if emitted.len() > max_bytes {
+            output.limit_exceeded = Some(LimitExceeded::OutputTruncated {
+                phase: Phase::Compile,
+                limit_bytes: max_bytes,
+            });
+        }
+        output.stdout = String::from_utf8_lossy(&emitted[..emitted.len().min(max_bytes)]).into();
+    }
 
     Ok(output)
 }
 
-async fn execute_code(code_base64: &str) -> anyhow::Result<String> {
-    let code = to_utf8(code_base64)?;
+/// The binary with the arguments and environment variables. It's run
+/// directly rather than with `cargo run` so the variables can't change how
+/// cargo builds
+fn program(
+    workspace: &Workspace,
+    params: &PlaygroundParams,
+    path: &Path,
+) -> anyhow::Result<Command> {
+    for key in params.env.keys() {
+        if key.is_empty() || key.contains(['=', '\0']) {
+            bail!(InvalidJob(format!(
+                "Invalid environment variable name `{}`",
+                key
+            )));
+        }
+    }
+
+    let mut command = Command::new(path);
+    command
+        .args(&params.args)
+        .envs(&params.env)
+        .current_dir(workspace.path());
+
+    Ok(command)
+}
+
+/// Name of the `src/main.rs` binary, the package's
+fn binary_name(workspace: &Workspace) -> anyhow::Result<String> {
+    let cargo_toml = fs::read_to_string(workspace.path().join("Cargo.toml"))?;
+    package_name(&cargo_toml).context("The playground Cargo.toml has no name")
+}
+
+/// Ends the output with the exit code when the program failed, the way
+/// `cargo run` reported it
+fn push_exit_status(output: &mut String, status: &ExitStatus) {
+    if status.success() {
+        return;
+    }
+
+    if !output.is_empty() && !output.ends_with('\n') {
+        output.push('\n');
+    }
 
-    let cwd = std::env::var("PROJECT_PATH").unwrap_or(PLAYGROUND_DIR.to_string());
-    let main_path = Path::new(&cwd).join("src/main.rs");
+    match (status.code(), status.signal()) {
+        (Some(code), _) => output.push_str(&format!("Process exited with code {}\n", code)),
+        (None, Some(signal)) => {
+            output.push_str(&format!("Process was killed by signal {}\n", signal))
+        }
+        (None, None) => {}
+    }
+}
+
+/// Writes the code to `src/main.rs` of a new workspace with the playground's
+/// Cargo.toml
+fn write_code(code: &str, files: &Files, edition: Option<Edition>) -> anyhow::Result<Workspace> {
+    let mut workspace = Workspace::new()?;
 
     // Write src/main.rs
-    write_file(&main_path, &code)?;
-    let output = run_command_and_merge_output("cargo", &["run"], Some(&cwd)).await?;
+    workspace.write("src/main.rs", code)?;
+    workspace.write_files(files)?;
+    workspace.write_playground_manifest(edition)?;
 
-    Ok(output)
+    Ok(workspace)
 }
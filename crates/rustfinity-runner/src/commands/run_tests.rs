@@ -1,162 +1,1012 @@
-use base64::prelude::*;
+use std::fs;
+use std::os::unix::process::ExitStatusExt;
 use std::path::Path;
 use std::process::Command;
 use std::time::Instant;
 
-use crate::constants::PLAYGROUND_DIR;
+use anyhow::{bail, Context};
+use toml::{Table, Value};
+
+use crate::benchmark::{
+    benchmark, compare, push_benchmark, push_comparison, BenchmarkOptions, FUNCTION_EXAMPLE,
+};
+use crate::cargo::{build, build_with_events, Build, TestExecutable};
+use crate::commands::TextOutput;
+use crate::diagnostics::Diagnostic;
+use crate::error::{InvalidJob, Outcome};
+use crate::events::{Event, Events};
+use crate::files::Files;
+use crate::libtest::{
+    listed_tests, parse_test_events, pretty_output, reported_results, suite_finished,
+    unfinished_tests, user_frames, JSON_ARGS, LIST_ARGS,
+};
+use crate::manifest::{lib_name, ManifestPolicy};
+use crate::memory::HeapProfiler;
+use crate::process::{run, run_merged, run_streaming, LimitExceeded, Limits, Phase, ProcessOutput};
 use crate::regex::extract_unittest_path;
-use crate::utils::{run_command_and_merge_output, write_file};
+use crate::sanitize::Sanitizer;
+use crate::scoring::Scoring;
+use crate::utils::{push_limit_exceeded, without_executable_lines, write_file};
+use crate::verdict::{
+    Benchmark, Comparison, CompileStatus, Score, TestExit, TestResult, TestStatus, Totals, Verdict,
+};
+use crate::workspace::Workspace;
+
+/// Where the hidden test suite is written, it's its own test target
+const HIDDEN_TESTS: &str = "tests/hidden.rs";
+/// Its target name
+const HIDDEN_TARGET: &str = "hidden";
 
 pub struct RunTestsParams {
-    code_base64: String,
-    tests_base64: String,
-    cargo_toml_base64: String,
-    n_tests: usize,
+    code: String,
+    tests: String,
+    cargo_toml: String,
+    hidden_tests: Option<String>,
+    scoring: Scoring,
+    files: Files,
+    benchmark: BenchmarkOptions,
+    reference: Option<String>,
+    manifest_policy: ManifestPolicy,
+    heap_profiler: HeapProfiler,
+    limits: Limits,
+    verbose: bool,
+    events: Events,
 }
 
 impl RunTestsParams {
-    pub fn new(
-        code_base64: String,
-        tests_base64: String,
-        cargo_toml_base64: String,
-        n_tests: Option<usize>,
-    ) -> Self {
+    pub fn new(code: String, tests: String, cargo_toml: String) -> Self {
         Self {
-            code_base64,
-            n_tests: n_tests.unwrap_or(1),
-            tests_base64,
-            cargo_toml_base64,
+            code,
+            tests,
+            cargo_toml,
+            hidden_tests: None,
+            scoring: Scoring::default(),
+            files: Files::new(),
+            benchmark: BenchmarkOptions::default(),
+            reference: None,
+            manifest_policy: ManifestPolicy::default(),
+            heap_profiler: HeapProfiler::default(),
+            limits: Limits::default(),
+            verbose: false,
+            events: Events::default(),
+        }
+    }
+
+    /// Extra files of the crate, e.g. modules of the library or more tests
+    pub fn with_files(mut self, files: Files) -> Self {
+        self.files = files;
+        self
+    }
+
+    /// Tests the learner doesn't see, their results only have the test's
+    /// name and status
+    pub fn with_hidden_tests(mut self, hidden_tests: Option<String>) -> Self {
+        self.hidden_tests = hidden_tests;
+        self
+    }
+
+    /// Weights and concept groups of the tests, every test weighs 1 without
+    pub fn with_scoring(mut self, scoring: Scoring) -> Self {
+        self.scoring = scoring;
+        self
+    }
+
+    /// Runs, warm-up runs and the function of the benchmark
+    pub fn with_benchmark(mut self, benchmark: BenchmarkOptions) -> Self {
+        self.benchmark = benchmark;
+        self
+    }
+
+    /// Code of a reference solution, benchmarked after the submission to
+    /// compare them
+    pub fn with_reference(mut self, reference: Option<String>) -> Self {
+        self.reference = reference;
+        self
+    }
+
+    pub fn with_manifest_policy(mut self, manifest_policy: ManifestPolicy) -> Self {
+        self.manifest_policy = manifest_policy;
+        self
+    }
+
+    pub fn with_heap_profiler(mut self, heap_profiler: HeapProfiler) -> Self {
+        self.heap_profiler = heap_profiler;
+        self
+    }
+
+    pub fn with_limits(mut self, limits: Limits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    /// Keeps cargo's build output and the runner's separators in the text
+    /// output
+    pub fn with_verbose(mut self, verbose: bool) -> Self {
+        self.verbose = verbose;
+        self
+    }
+
+    /// Receives the progress of [`run_tests`](crate::run_tests) as it happens
+    pub fn with_events(mut self, events: Events) -> Self {
+        self.events = events;
+        self
+    }
+
+    /// The files of the submission, where the backtraces are cut down to
+    fn user_files(&self) -> Vec<String> {
+        std::iter::once("src/lib.rs".to_string())
+            .chain(self.files.keys().cloned())
+            .collect()
+    }
+}
+
+/// Cargo's output followed by the benchmark and the score
+pub async fn run_tests(params: &RunTestsParams) -> anyhow::Result<TextOutput> {
+    let workspace = write_project(
+        &params.code,
+        &params.tests,
+        &params.cargo_toml,
+        params.hidden_tests.is_some(),
+        &params.files,
+        &params.manifest_policy,
+        params.heap_profiler,
+    )?;
+    let TestsOutput {
+        mut output,
+        outcome,
+        tests,
+        completed,
+    } = tests_output(params, &workspace).await?;
+
+    let outcome = match outcome {
+        Outcome::Passed if tests.iter().any(|test| test.status == TestStatus::Failed) => {
+            Outcome::Failed
+        }
+        outcome => outcome,
+    };
+    if params.scoring.is_weighted(&tests) {
+        let completed = completed && matches!(outcome, Outcome::Passed | Outcome::Failed);
+        push_score(&mut output, &params.scoring.score(&tests, completed));
+    }
+
+    let user_files = params.user_files();
+    let mut sanitizer =
+        Sanitizer::new(workspace.path(), params.verbose).with_user_files(&user_files);
+    if !params.verbose {
+        sanitizer = sanitizer.without_separators();
+    }
+    Ok(TextOutput {
+        output: sanitizer.sanitize(&output),
+        outcome,
+    })
+}
+
+/// The output of [`run_tests`] without the score, and how the run ended.
+/// Failed tests are left to the caller, the output has them
+struct TestsOutput {
+    output: String,
+    outcome: Outcome,
+    /// The public and hidden results, from libtest's JSON events
+    tests: Vec<TestResult>,
+    /// Whether every test binary ran to its end
+    completed: bool,
+}
+
+impl TestsOutput {
+    /// A run that stopped before all the tests ran
+    fn stopped(output: String, outcome: Outcome, tests: Vec<TestResult>) -> Self {
+        Self {
+            output,
+            outcome,
+            tests,
+            completed: false,
         }
     }
 }
 
-pub async fn run_tests(params: &RunTestsParams) -> anyhow::Result<String> {
+async fn tests_output(
+    params: &RunTestsParams,
+    workspace: &Workspace,
+) -> anyhow::Result<TestsOutput> {
     let RunTestsParams {
-        code_base64,
-        n_tests,
-        tests_base64,
-        cargo_toml_base64,
+        benchmark: benchmark_options,
+        reference,
+        hidden_tests,
+        heap_profiler,
+        limits,
+        ..
     } = params;
 
     let mut output = String::new();
 
-    let tests_output = execute_code(&code_base64, &tests_base64, &cargo_toml_base64).await?;
-    output.push_str(&tests_output);
+    let execution = execute_code(workspace, limits).await?;
+    output.push_str(&execution.output);
+
+    let mut tests = execution.tests;
+
+    if let Some(limit_exceeded) = execution.limit_exceeded {
+        return Ok(TestsOutput::stopped(
+            output,
+            Outcome::from_limit(&limit_exceeded),
+            tests,
+        ));
+    }
+    if !execution.compiled {
+        return Ok(TestsOutput::stopped(output, Outcome::CompileError, tests));
+    }
 
     let test_binary_path = extract_unittest_path(&output);
+    let mut completed = !execution.crashed;
+
+    if let Some(hidden_tests) = hidden_tests {
+        let hidden = run_hidden_tests(workspace, hidden_tests, limits, &Events::default()).await?;
+        push_hidden_tests(&mut output, &hidden);
+        tests.extend(hidden.tests);
+
+        if let Some(limit_exceeded) = hidden.limit_exceeded {
+            push_limit_exceeded(&mut output, &limit_exceeded);
+            return Ok(TestsOutput::stopped(
+                output,
+                Outcome::from_limit(&limit_exceeded),
+                tests,
+            ));
+        }
+        if !hidden.compiled {
+            return Ok(TestsOutput::stopped(output, Outcome::CompileError, tests));
+        }
+        completed &= !hidden.exit.as_ref().is_some_and(TestExit::crashed);
+    }
 
-    if let Some(test_binary_path) = test_binary_path {
-        let time_output = benchmark_time_min(&test_binary_path, n_tests).await?;
-        let memory_output = memory_benchmark(&test_binary_path).await?;
+    // A test binary can also crash without a failed test
+    let mut outcome = match execution.passed {
+        true => Outcome::Passed,
+        false => Outcome::Failed,
+    };
 
-        output.push_str("\n");
-        output.push_str("---");
-        output.push_str("\n");
-        output.push_str(time_output.as_str());
-        output.push_str("\n");
-        output.push_str(memory_output.as_str());
+    if let Some(test_binary_path) = test_binary_path {
+        match benchmark(
+            workspace,
+            &test_binary_path,
+            execution.compile_time_ms,
+            benchmark_options,
+            *heap_profiler,
+            limits,
+        )
+        .await
+        {
+            Ok(benchmark) => {
+                push_benchmark(&mut output, &benchmark);
+
+                if let Some(reference) = reference {
+                    match compare_with_reference(
+                        workspace,
+                        reference,
+                        &benchmark,
+                        benchmark_options,
+                        *heap_profiler,
+                        limits,
+                    )
+                    .await
+                    {
+                        Ok(comparison) => {
+                            push_comparison(&mut output, &comparison);
+                            if comparison.passed == Some(false) {
+                                outcome = Outcome::Failed;
+                            }
+                        }
+                        Err(e) => {
+                            let limit_exceeded = e.downcast::<LimitExceeded>()?;
+                            push_limit_exceeded(&mut output, &limit_exceeded);
+                            outcome = Outcome::from_limit(&limit_exceeded);
+                        }
+                    }
+                }
+            }
+            Err(e) => {
+                let limit_exceeded = e.downcast::<LimitExceeded>()?;
+                push_limit_exceeded(&mut output, &limit_exceeded);
+                outcome = Outcome::from_limit(&limit_exceeded);
+            }
+        }
     }
 
-    Ok(output)
+    Ok(TestsOutput {
+        output,
+        outcome,
+        tests,
+        completed,
+    })
 }
 
-async fn benchmark_time(test_binary_path: &str) -> anyhow::Result<f64> {
-    let cwd = std::env::var("PROJECT_PATH").unwrap_or(PLAYGROUND_DIR.to_string());
+/// Same as [`run_tests`] but returns a structured verdict instead of the raw
+/// cargo output, the test binaries are run with libtest's JSON output
+pub async fn run_tests_verdict(params: &RunTestsParams) -> anyhow::Result<Verdict> {
+    let RunTestsParams {
+        code,
+        benchmark: benchmark_options,
+        reference,
+        tests,
+        cargo_toml,
+        hidden_tests,
+        scoring,
+        files,
+        manifest_policy,
+        heap_profiler,
+        limits,
+        events,
+        ..
+    } = params;
+
+    let workspace = write_project(
+        code,
+        tests,
+        cargo_toml,
+        hidden_tests.is_some(),
+        files,
+        manifest_policy,
+        *heap_profiler,
+    )?;
+
+    let compile_start = Instant::now();
+    let Build {
+        messages,
+        output: mut compile_output,
+        limit_exceeded,
+    } = build_with_events(
+        workspace.cargo().args(["test", "--no-run"]),
+        &workspace.files(),
+        limits,
+        events,
+    )?;
+    let compile_time_ms = compile_start.elapsed().as_secs_f64() * 1000.0;
+
+    events.emit(&Event::BuildFinished {
+        success: messages.success && limit_exceeded.is_none(),
+        duration_ms: compile_time_ms,
+        diagnostics: &messages.diagnostics,
+    });
 
-    let start = Instant::now();
+    if !messages.success || limit_exceeded.is_some() {
+        return Ok(Verdict {
+            compile: CompileStatus::Failed,
+            compile_output,
+            diagnostics: messages.diagnostics,
+            tests: Vec::new(),
+            totals: Totals::default(),
+            score: scoring.score(&[], false),
+            benchmark: None,
+            comparison: None,
+            test_exits: Vec::new(),
+            limit_exceeded,
+            cached: false,
+        });
+    }
 
-    Command::new(&test_binary_path).current_dir(&cwd).output()?;
+    let mut compile = CompileStatus::Success;
+    let mut diagnostics = messages.diagnostics;
+    let user_files = params.user_files();
+    let mut tests = Vec::new();
+    let mut test_exits = Vec::new();
+    let mut limit_exceeded = None;
+
+    for executable in &messages.test_executables {
+        let listed = list_tests(&workspace, &executable.path, limits)?;
+        let events = events.clone();
+        let output = run_streaming(
+            Command::new(&executable.path)
+                .args(JSON_ARGS)
+                .env("RUSTC_BOOTSTRAP", "1")
+                .env("RUST_BACKTRACE", "1")
+                .current_dir(workspace.path()),
+            Phase::Run,
+            limits,
+            move |line| events.test_line(line, false),
+        )?;
+
+        let (results, exit) = test_results(&executable.name, &output, &listed);
+        tests.extend(results.into_iter().map(|test| TestResult {
+            backtrace: user_frames(&test.backtrace, &user_files),
+            ..test
+        }));
+        test_exits.push(exit);
+
+        if output.limit_exceeded.is_some() {
+            limit_exceeded = output.limit_exceeded;
+            break;
+        }
+    }
 
-    let elapsed = start.elapsed();
-    let as_nanos = elapsed.as_nanos();
+    // The submission has to compile with the hidden tests too
+    if let (Some(hidden_tests), None) = (hidden_tests, limit_exceeded) {
+        let hidden = run_hidden_tests(&workspace, hidden_tests, limits, events).await?;
 
-    let as_ms = as_nanos as f64 / 1_000_000.0;
+        if !hidden.compiled {
+            compile = CompileStatus::Failed;
+            push_hidden_tests(&mut compile_output, &hidden);
+        }
+        diagnostics.extend(hidden.errors);
+        tests.extend(hidden.tests);
+        test_exits.extend(hidden.exit);
+        limit_exceeded = hidden.limit_exceeded;
+    }
 
-    Ok(as_ms)
+    let tests_binary = messages
+        .test_executables
+        .iter()
+        .find(|executable| executable.src_path.ends_with("tests/tests.rs"));
+
+    let benchmark = match (tests_binary, limit_exceeded, compile) {
+        (Some(executable), None, CompileStatus::Success) => {
+            match benchmark(
+                &workspace,
+                &executable.path,
+                compile_time_ms,
+                benchmark_options,
+                *heap_profiler,
+                limits,
+            )
+            .await
+            {
+                Ok(benchmark) => {
+                    events.emit(&Event::BenchmarkResult(&benchmark));
+                    Some(benchmark)
+                }
+                Err(e) => {
+                    limit_exceeded = Some(e.downcast::<LimitExceeded>()?);
+                    None
+                }
+            }
+        }
+        _ => None,
+    };
+
+    let comparison = match (&benchmark, reference) {
+        (Some(benchmark), Some(reference)) => {
+            match compare_with_reference(
+                &workspace,
+                reference,
+                benchmark,
+                benchmark_options,
+                *heap_profiler,
+                limits,
+            )
+            .await
+            {
+                Ok(comparison) => {
+                    events.emit(&Event::ComparisonResult(&comparison));
+                    Some(comparison)
+                }
+                Err(e) => {
+                    limit_exceeded = Some(e.downcast::<LimitExceeded>()?);
+                    None
+                }
+            }
+        }
+        _ => None,
+    };
+
+    let completed = compile == CompileStatus::Success
+        && limit_exceeded.is_none()
+        && !test_exits.iter().any(TestExit::crashed);
+
+    Ok(Verdict {
+        compile,
+        compile_output,
+        diagnostics,
+        totals: Totals::from_tests(&tests),
+        score: scoring.score(&tests, completed),
+        tests,
+        benchmark,
+        comparison,
+        test_exits,
+        limit_exceeded,
+        cached: false,
+    })
 }
 
-/// Runs the tests 10 times and gets the minimum time
-async fn benchmark_time_min(test_binary_path: &str, n_tests: &usize) -> anyhow::Result<String> {
-    let mut nums = Vec::with_capacity(10);
+/// Builds the reference solution in place of the submission, in the same
+/// workspace, and benchmarks it the same way. Fails with [`LimitExceeded`] if
+/// one of its runs hits a limit
+async fn compare_with_reference(
+    workspace: &Workspace,
+    reference: &str,
+    submission: &Benchmark,
+    benchmark_options: &BenchmarkOptions,
+    heap_profiler: HeapProfiler,
+    limits: &Limits,
+) -> anyhow::Result<Comparison> {
+    let cargo_toml = fs::read_to_string(workspace.path().join("Cargo.toml"))?;
+    let reference = benchmark_reference(
+        workspace,
+        reference,
+        benchmark_options,
+        heap_profiler,
+        limits,
+    )
+    .await;
+
+    // The reference solution is in the crate's artifacts and in the
+    // benchmark's, the slot's next job can't read it
+    let mut targets = vec!["tests", FUNCTION_EXAMPLE];
+    let lib_name = lib_name(&cargo_toml);
+    targets.extend(lib_name.as_deref());
+    workspace.remove_artifacts(&targets)?;
+
+    Ok(compare(submission, reference?, benchmark_options))
+}
 
-    for _ in 0..*n_tests {
-        let time = benchmark_time(&test_binary_path).await?;
-        nums.push(time);
+/// Builds the reference solution in place of the submission and benchmarks
+/// it the same way
+async fn benchmark_reference(
+    workspace: &Workspace,
+    reference: &str,
+    benchmark_options: &BenchmarkOptions,
+    heap_profiler: HeapProfiler,
+    limits: &Limits,
+) -> anyhow::Result<Benchmark> {
+    write_file(&workspace.path().join("src/lib.rs"), reference)?;
+
+    let compile_start = Instant::now();
+    let Build {
+        messages,
+        output,
+        limit_exceeded,
+    } = build(
+        workspace
+            .cargo()
+            .env("CARGO_INCREMENTAL", "0")
+            .args(["test", "--no-run"]),
+        &workspace.files(),
+        limits,
+    )?;
+    let compile_time_ms = compile_start.elapsed().as_secs_f64() * 1000.0;
+
+    if let Some(limit_exceeded) = limit_exceeded {
+        return Err(limit_exceeded.into());
     }
 
-    // Take Average
-    let min = nums
+    let tests_binary = messages
+        .test_executables
         .iter()
-        .min_by(|a, b| a.partial_cmp(b).unwrap())
-        .unwrap();
+        .find(|executable| executable.src_path.ends_with("tests/tests.rs"))
+        .filter(|_| messages.success)
+        .with_context(|| {
+            InvalidJob(format!(
+                "Failed to compile the reference solution:\n{}",
+                output
+            ))
+        })?;
+
+    benchmark(
+        workspace,
+        &tests_binary.path,
+        compile_time_ms,
+        benchmark_options,
+        heap_profiler,
+        limits,
+    )
+    .await
+}
+
+/// Output of [`execute_code`]
+struct Execution {
+    /// Cargo's output followed by the output of each test binary, and the
+    /// limit that was hit
+    output: String,
+    compiled: bool,
+    /// Whether every test binary succeeded
+    passed: bool,
+    tests: Vec<TestResult>,
+    /// Whether a test binary stopped before the end of its tests
+    crashed: bool,
+    limit_exceeded: Option<LimitExceeded>,
+    compile_time_ms: f64,
+}
 
-    let final_output = format!("Time: {:.8}ms", min);
+/// Compiles the tests with `cargo test --no-run` and runs each test binary it
+/// reports, each step with its own limits. Only the test binaries run in the
+/// jail, cargo doesn't
+async fn execute_code(workspace: &Workspace, limits: &Limits) -> anyhow::Result<Execution> {
+    let compile_start = Instant::now();
+    let Build {
+        messages,
+        output: compile_output,
+        limit_exceeded,
+    } = build(
+        workspace.cargo().args(["test", "--no-run"]),
+        &workspace.files(),
+        limits,
+    )?;
+    let compile_time_ms = compile_start.elapsed().as_secs_f64() * 1000.0;
+
+    if !messages.success || limit_exceeded.is_some() {
+        return Ok(Execution {
+            output: compile_output,
+            compiled: false,
+            passed: false,
+            tests: Vec::new(),
+            crashed: false,
+            limit_exceeded,
+            compile_time_ms,
+        });
+    }
 
-    Ok(final_output)
+    let mut execution = Execution {
+        output: without_executable_lines(&compile_output),
+        compiled: true,
+        passed: true,
+        tests: Vec::new(),
+        crashed: false,
+        limit_exceeded: None,
+        compile_time_ms,
+    };
+
+    for executable in &messages.test_executables {
+        // Printed like cargo does, the sanitizer maps the binaries back to
+        // their sources with it
+        execution.output.push_str(&format!(
+            "     Running {} ({})\n",
+            running_name(workspace, executable),
+            executable.path
+        ));
+
+        // Run with the JSON output so the results come from libtest, printed
+        // the way it does. The backtraces of the failed tests are cut down by
+        // the sanitizer
+        let listed = list_tests(workspace, &executable.path, limits)?;
+        let output = run_merged(
+            Command::new(&executable.path)
+                .args(JSON_ARGS)
+                .env("RUSTC_BOOTSTRAP", "1")
+                .env("RUST_BACKTRACE", "1")
+                .current_dir(workspace.path()),
+            Phase::Run,
+            limits,
+        )?;
+        execution.output.push_str(&pretty_output(&output.stdout));
+        execution.passed &= output.success();
+
+        let (tests, exit) = test_results(&executable.name, &output, &listed);
+        execution.tests.extend(tests);
+
+        if let Some(limit_exceeded) = output.limit_exceeded {
+            push_limit_exceeded(&mut execution.output, &limit_exceeded);
+            execution.crashed = true;
+            execution.limit_exceeded = Some(limit_exceeded);
+            break;
+        }
+
+        if exit.crashed() {
+            execution.crashed = true;
+            if !execution.output.ends_with('\n') {
+                execution.output.push('\n');
+            }
+            execution
+                .output
+                .push_str(&format!("error: {}\n", exit_message(&exit)));
+        }
+    }
+
+    Ok(execution)
 }
 
-async fn memory_benchmark(test_binary_path: &str) -> anyhow::Result<String> {
-    let cwd = std::env::var("PROJECT_PATH").unwrap_or(PLAYGROUND_DIR.to_string());
+/// The name cargo gives a test binary in its `Running` lines, e.g.
+/// `unittests src/lib.rs` or `tests/tests.rs`
+fn running_name(workspace: &Workspace, executable: &TestExecutable) -> String {
+    let src_path = Path::new(&executable.src_path);
+    let src_path = src_path
+        .strip_prefix(workspace.path())
+        .unwrap_or(src_path)
+        .display();
+
+    if executable.kind.iter().any(|kind| kind == "test") {
+        src_path.to_string()
+    } else {
+        format!("unittests {}", src_path)
+    }
+}
 
-    let output = Command::new("heaptrack")
-        .arg(&test_binary_path)
-        .current_dir(&cwd)
-        .output()?;
+/// Results of the hidden suite, without anything that would show its code
+struct HiddenTests {
+    compiled: bool,
+    /// The errors of its build, without the source lines
+    errors: Vec<Diagnostic>,
+    /// Only with their name and status
+    tests: Vec<TestResult>,
+    /// `None` when it didn't compile
+    exit: Option<TestExit>,
+    limit_exceeded: Option<LimitExceeded>,
+}
 
-    let stdout = String::from_utf8(output.stdout)?;
+/// Builds the hidden suite on its own, once the submission compiled with
+/// the public tests, and runs it
+async fn run_hidden_tests(
+    workspace: &Workspace,
+    hidden_tests: &str,
+    limits: &Limits,
+    events: &Events,
+) -> anyhow::Result<HiddenTests> {
+    // Written only for its build, the public tests ran without it so the
+    // submission couldn't read it
+    let manifest_path = workspace.path().join("Cargo.toml");
+    let cargo_toml = fs::read_to_string(&manifest_path)?;
+    write_file(&manifest_path, &with_hidden_test_target(&cargo_toml)?)?;
+    write_file(&workspace.path().join(HIDDEN_TESTS), hidden_tests)?;
+
+    // Its spans aren't mapped, it isn't one of the workspace's files, the
+    // rendered diagnostics would show its code. Without the incremental
+    // cache, which would keep its code in the slot
+    let build = build(
+        workspace.cargo().env("CARGO_INCREMENTAL", "0").args([
+            "test",
+            "--no-run",
+            "--test",
+            HIDDEN_TARGET,
+        ]),
+        &workspace.files(),
+        limits,
+    );
+
+    // Gone before the submission's code runs again, with the hidden binary
+    // or the benchmarks
+    fs::remove_file(workspace.path().join(HIDDEN_TESTS))?;
+    write_file(&manifest_path, &cargo_toml)?;
+
+    let Build {
+        messages,
+        limit_exceeded,
+        ..
+    } = build?;
+
+    // The warnings are the submission's, already reported with the public tests
+    let errors = messages
+        .diagnostics
+        .into_iter()
+        .filter(|diagnostic| diagnostic.level == "error")
+        .map(Diagnostic::redacted)
+        .collect();
+
+    let executable = messages.test_executables.first();
+    let (Some(executable), true, None) = (executable, messages.success, limit_exceeded) else {
+        workspace.remove_artifacts(&[HIDDEN_TARGET])?;
+        return Ok(HiddenTests {
+            compiled: false,
+            errors,
+            tests: Vec::new(),
+            exit: None,
+            limit_exceeded,
+        });
+    };
+
+    let listed = list_tests(workspace, &executable.path, limits)?;
+    let events = events.clone();
+    let output = run_streaming(
+        Command::new(&executable.path)
+            .args(JSON_ARGS)
+            .env("RUSTC_BOOTSTRAP", "1")
+            .current_dir(workspace.path()),
+        Phase::Run,
+        limits,
+        move |line| events.test_line(line, true),
+    )?;
+
+    // The slot's next run can't read it either
+    workspace.remove_artifacts(&[HIDDEN_TARGET])?;
+
+    let (tests, exit) = test_results(HIDDEN_TARGET, &output, &listed);
+
+    Ok(HiddenTests {
+        compiled: true,
+        errors,
+        tests: tests.into_iter().map(TestResult::redacted).collect(),
+        exit: Some(exit),
+        limit_exceeded: output.limit_exceeded,
+    })
+}
 
-    let output_path = stdout.split("\"").collect::<Vec<&str>>()[1];
+/// The tests of a test binary, listed before the code runs so that results
+/// it prints itself can be told apart from libtest's
+fn list_tests(
+    workspace: &Workspace,
+    executable: &str,
+    limits: &Limits,
+) -> anyhow::Result<Vec<String>> {
+    let output = run(
+        Command::new(executable)
+            .args(LIST_ARGS)
+            .current_dir(workspace.path()),
+        Phase::Run,
+        limits,
+    )?;
+
+    Ok(listed_tests(&output.stdout))
+}
 
-    // run heaptrack --analyze {output_path}
-    let output = Command::new("heaptrack")
-        .arg("--analyze")
-        .arg(output_path)
-        .output()?;
+/// The results of a test binary run with libtest's JSON output, and how it
+/// exited. Only the `listed` tests count, once each. When it died partway
+/// through, e.g. on a crash or the code exiting the process, the tests it
+/// didn't finish failed
+fn test_results(
+    target: &str,
+    output: &ProcessOutput,
+    listed: &[String],
+) -> (Vec<TestResult>, TestExit) {
+    let mut tests = reported_results(parse_test_events(&output.stdout), listed);
+    let exit = TestExit {
+        target: target.to_string(),
+        exit_code: output.status.code(),
+        signal: output.status.signal(),
+        completed: suite_finished(&output.stdout),
+    };
+
+    if exit.crashed() && output.limit_exceeded.is_none() {
+        let message = format!("{} during the test", exit_message(&exit));
+
+        tests.extend(
+            unfinished_tests(&output.stdout)
+                .into_iter()
+                .filter(|name| listed.contains(name))
+                .map(|name| TestResult {
+                    name,
+                    status: TestStatus::Failed,
+                    message: Some(message.clone()),
+                    backtrace: Vec::new(),
+                    duration_ms: None,
+                    hidden: false,
+                }),
+        );
+    }
 
-    let stdout = String::from_utf8(output.stdout)?;
+    (tests, exit)
+}
 
-    let details = stdout.split("\n\n").collect::<Vec<&str>>();
-    let last_index = details.len() - 1;
-    let details = details[last_index];
+/// How a test binary that stopped before the end of its tests exited
+fn exit_message(exit: &TestExit) -> String {
+    match (exit.exit_code, exit.signal) {
+        (Some(code), _) => format!("The test binary exited with code {}", code),
+        (None, Some(signal)) => format!("The test binary was killed by signal {}", signal),
+        (None, None) => "The test binary exited".to_string(),
+    }
+}
 
-    let mut memory = String::new();
+/// The results of the hidden tests the way libtest prints them, without
+/// their output, or their redacted errors when they didn't compile
+fn push_hidden_tests(output: &mut String, hidden: &HiddenTests) {
+    if !output.is_empty() && !output.ends_with('\n') {
+        output.push('\n');
+    }
 
-    details.lines().for_each(|line| {
-        if line.contains("peak heap memory consumption:") {
-            memory = line.replace("peak", "Peak");
+    if !hidden.compiled {
+        if hidden.limit_exceeded.is_none() {
+            output.push_str("\nThe hidden tests failed to compile:\n");
+            for error in &hidden.errors {
+                output.push_str(&error.rendered);
+            }
         }
-    });
+        return;
+    }
 
-    Ok(memory)
+    output.push_str("\nHidden tests:\n");
+    for test in &hidden.tests {
+        let status = match test.status {
+            TestStatus::Passed => "ok",
+            TestStatus::Failed => "FAILED",
+            TestStatus::Ignored => "ignored",
+        };
+        output.push_str(&format!("test {} ... {}\n", test.name, status));
+    }
+
+    let totals = Totals::from_tests(&hidden.tests);
+    output.push_str(&format!(
+        "\nhidden test result: {}. {} passed; {} failed; {} ignored\n",
+        if totals.failed == 0 { "ok" } else { "FAILED" },
+        totals.passed,
+        totals.failed,
+        totals.ignored
+    ));
 }
 
-fn to_utf8(base64: &str) -> anyhow::Result<String> {
-    let utf8 = BASE64_STANDARD.decode(base64)?;
-    Ok(String::from_utf8(utf8)?)
+/// The score after the rest of the output, with the concept groups missed
+fn push_score(output: &mut String, score: &Score) {
+    output.push_str("\n---\n");
+    output.push_str(&format!(
+        "Score: {}/100 ({} of {} points)\n",
+        score.score, score.earned, score.total
+    ));
+
+    if !score.missed_groups.is_empty() {
+        output.push_str(&format!(
+            "Missed concepts: {}\n",
+            score.missed_groups.join(", ")
+        ));
+    }
 }
 
-async fn execute_code(
-    code_base64: &str,
-    tests_base64: &str,
-    config_toml_base64: &str,
-) -> anyhow::Result<String> {
-    let code = to_utf8(code_base64)?;
-    let tests = to_utf8(tests_base64)?;
-    let config_toml = to_utf8(config_toml_base64)?;
+/// Writes the code, tests, extra files and Cargo.toml to a new workspace,
+/// fails with [`PolicyViolation`](crate::manifest::PolicyViolation) if the Cargo.toml isn't allowed.
+/// The tests get the heap profiler's counting allocator if it uses one. The
+/// hidden tests are only written by [`run_hidden_tests`], their path is
+/// reserved
+fn write_project(
+    code: &str,
+    tests: &str,
+    config_toml: &str,
+    hidden_tests: bool,
+    files: &Files,
+    manifest_policy: &ManifestPolicy,
+    heap_profiler: HeapProfiler,
+) -> anyhow::Result<Workspace> {
+    let config_toml = manifest_policy.apply(config_toml)?;
+
+    if hidden_tests && files.contains_key(HIDDEN_TESTS) {
+        bail!(InvalidJob(format!(
+            "`{}` is reserved for the hidden tests",
+            HIDDEN_TESTS
+        )));
+    }
 
-    let cwd = std::env::var("PROJECT_PATH").unwrap_or(PLAYGROUND_DIR.to_string());
-    let tests_path = Path::new(&cwd).join("tests/tests.rs");
-    let config_toml_path = Path::new(&cwd).join("Cargo.toml");
-    let lib_path = Path::new(&cwd).join("src/lib.rs");
+    let mut workspace = Workspace::new()?;
 
     // Write src/lib.rs
-    write_file(&lib_path, &code)?;
+    workspace.write("src/lib.rs", code)?;
     // Write tests/tests.rs
-    write_file(&tests_path, &tests)?;
+    let sources = std::iter::once(code)
+        .chain(files.values().map(String::as_str))
+        .collect::<Vec<_>>();
+    workspace.write(
+        "tests/tests.rs",
+        &heap_profiler.instrument_tests(tests, &sources),
+    )?;
+    workspace.write_files(files)?;
     // Write Cargo.toml
-    write_file(&config_toml_path, &config_toml)?;
+    workspace.write_manifest(&config_toml)?;
 
-    let output = run_command_and_merge_output("cargo", &["test"], Some(&cwd)).await?;
+    Ok(workspace)
+}
 
-    Ok(output)
+/// Declares the hidden suite as a test target `cargo test` leaves out, it's
+/// built and run on its own so its output can be redacted
+fn with_hidden_test_target(cargo_toml: &str) -> anyhow::Result<String> {
+    let mut manifest = cargo_toml
+        .parse::<Table>()
+        .context(InvalidJob("Invalid Cargo.toml".to_string()))?;
+
+    let mut target = Table::new();
+    target.insert("name".to_string(), Value::from(HIDDEN_TARGET));
+    target.insert("path".to_string(), Value::from(HIDDEN_TESTS));
+    target.insert("test".to_string(), Value::from(false));
+
+    manifest
+        .entry("test")
+        .or_insert_with(|| Value::Array(Vec::new()))
+        .as_array_mut()
+        .ok_or_else(|| InvalidJob("`test` in the Cargo.toml isn't an array of tables".to_string()))?
+        .push(Value::Table(target));
+
+    Ok(manifest.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::RunnerError;
+
+    #[test]
+    fn test_declares_the_hidden_test_target() {
+        let manifest = with_hidden_test_target(
+            "[package]\nname = \"fibonacci\"\n\n[[test]]\nname = \"tests\"\npath = \"tests/tests.rs\"\n",
+        )
+        .unwrap()
+        .parse::<Table>()
+        .unwrap();
+
+        let targets = manifest["test"].as_array().unwrap();
+        assert_eq!(targets.len(), 2);
+        assert_eq!(targets[1]["name"].as_str(), Some("hidden"));
+        assert_eq!(targets[1]["path"].as_str(), Some(HIDDEN_TESTS));
+        assert_eq!(targets[1]["test"].as_bool(), Some(false));
+
+        let error = with_hidden_test_target("test = 1").unwrap_err();
+        assert_eq!(RunnerError::from(error).kind(), "invalid_job");
+    }
 }
@@ -1,99 +1,276 @@
 use anyhow::{Context, Result};
-use base64::prelude::*;
+use serde::Serialize;
 use std::fs;
+use std::path::Path;
 use std::process::Command;
 use tempfile::TempDir;
 
+use crate::cargo::{build, TestExecutable};
+use crate::diagnostics::Diagnostic;
+use crate::error::Outcome;
+use crate::files::{check_path, Files};
+use crate::process::{run, run_merged, LimitExceeded, Limits, Phase};
+use crate::sanitize::{strip_colors, Sanitizer};
+use crate::utils::{push_limit_exceeded, without_executable_lines, write_file};
+
+const MAIN_FILE: &str = "src/main.rs";
+/// The exercise's binary built by `cargo build`, in the project's own
+/// target directory
+const BINARY: &str = "target/debug/rustlings_exercise";
+
+/// How an exercise is checked
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum RustlingsMode {
+    /// Runs its tests
+    #[default]
+    Test,
+    /// Compiles it then runs it to show its output
+    Check,
+}
+
 /// Parameters for running a rustlings exercise
 pub struct RustlingsParams {
     pub code: String,
+    pub mode: RustlingsMode,
+    /// Extra files of the exercise's crate
+    pub files: Files,
+    /// Collect the compiler diagnostics through cargo's JSON messages
+    pub diagnostics: bool,
+    pub limits: Limits,
+    /// Keep cargo's build output
+    pub verbose: bool,
 }
 
 impl RustlingsParams {
-    pub fn new(code_base64: String) -> Self {
-        Self { code: code_base64 }
+    pub fn new(code: String) -> Self {
+        Self {
+            code,
+            mode: RustlingsMode::default(),
+            files: Files::new(),
+            diagnostics: false,
+            limits: Limits::default(),
+            verbose: false,
+        }
+    }
+
+    pub fn with_mode(mut self, mode: RustlingsMode) -> Self {
+        self.mode = mode;
+        self
+    }
+
+    pub fn with_limits(mut self, limits: Limits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    pub fn with_files(mut self, files: Files) -> Self {
+        self.files = files;
+        self
+    }
+
+    pub fn with_diagnostics(mut self, diagnostics: bool) -> Self {
+        self.diagnostics = diagnostics;
+        self
     }
 
-    fn decode_code(&self) -> Result<String> {
-        let bytes = BASE64_STANDARD
-            .decode(&self.code)
-            .context("Failed to decode code from base64")?;
-        String::from_utf8(bytes).context("Code is not valid UTF-8")
+    pub fn with_verbose(mut self, verbose: bool) -> Self {
+        self.verbose = verbose;
+        self
     }
 }
 
 /// Result of running a rustlings exercise
+#[derive(Debug, Serialize)]
 pub struct RustlingsResult {
     pub output: String,
     pub success: bool,
+    /// Whether the exercise compiled, the tests or the program can still fail
+    pub compiled: bool,
+    pub diagnostics: Vec<Diagnostic>,
+    pub limit_exceeded: Option<LimitExceeded>,
+}
+
+impl RustlingsResult {
+    pub fn outcome(&self) -> Outcome {
+        match &self.limit_exceeded {
+            Some(limit_exceeded) => Outcome::from_limit(limit_exceeded),
+            None if !self.compiled => Outcome::CompileError,
+            None if !self.success => Outcome::Failed,
+            None => Outcome::Passed,
+        }
+    }
+}
+
+/// Run rustlings exercise in its mode
+pub async fn run_rustlings(params: &RustlingsParams) -> Result<RustlingsResult> {
+    match params.mode {
+        RustlingsMode::Test => run_rustlings_test(params).await,
+        RustlingsMode::Check => run_rustlings_check(params).await,
+    }
 }
 
 /// Run rustlings exercise with cargo test
-pub async fn run_rustlings_test(params: &RustlingsParams) -> Result<RustlingsResult> {
-    let code = params.decode_code()?;
-    let temp_dir = create_rustlings_project(&code)?;
-
-    // Run cargo test
-    let output = Command::new("cargo")
-        .args(["test", "--", "--nocapture"])
-        .current_dir(temp_dir.path())
-        .output()
+async fn run_rustlings_test(params: &RustlingsParams) -> Result<RustlingsResult> {
+    let temp_dir = create_rustlings_project(&params.code, &params.files)?;
+
+    let compiled = compile(temp_dir.path(), &["test", "--no-run"], params)
         .context("Failed to run cargo test")?;
 
-    let stdout = String::from_utf8_lossy(&output.stdout);
-    let stderr = String::from_utf8_lossy(&output.stderr);
+    if !compiled.success {
+        return Ok(compiled.into_result());
+    }
+
+    // Only the test binaries run in the jail, not cargo
+    let mut result_output = compiled.output;
+    let mut success = true;
+    let mut limit_exceeded = None;
+
+    for executable in &compiled.test_executables {
+        let output = run_merged(
+            Command::new(&executable.path)
+                .arg("--nocapture")
+                .current_dir(temp_dir.path()),
+            Phase::Run,
+            &params.limits,
+        )
+        .context("Failed to run the tests")?;
+
+        result_output
+            .push_str(&Sanitizer::new(temp_dir.path(), params.verbose).sanitize(&output.stdout));
+        success &= output.success();
+
+        if let Some(limit) = output.limit_exceeded {
+            push_limit_exceeded(&mut result_output, &limit);
+            limit_exceeded = Some(limit);
+            break;
+        }
+    }
 
     Ok(RustlingsResult {
-        output: format!("{}{}", stderr, stdout),
-        success: output.status.success(),
+        output: result_output,
+        success,
+        compiled: true,
+        diagnostics: compiled.diagnostics,
+        limit_exceeded,
     })
 }
 
 /// Run rustlings exercise with cargo check (compilation only)
-pub async fn run_rustlings_check(params: &RustlingsParams) -> Result<RustlingsResult> {
-    let code = params.decode_code()?;
-    let temp_dir = create_rustlings_project(&code)?;
+async fn run_rustlings_check(params: &RustlingsParams) -> Result<RustlingsResult> {
+    let temp_dir = create_rustlings_project(&params.code, &params.files)?;
 
     // Run cargo check
-    let output = Command::new("cargo")
-        .args(["check"])
-        .current_dir(temp_dir.path())
-        .output()
-        .context("Failed to run cargo check")?;
-
-    let stdout = String::from_utf8_lossy(&output.stdout);
-    let stderr = String::from_utf8_lossy(&output.stderr);
-
-    // If check succeeded, also run the code to show output
-    if output.status.success() {
-        let run_output = Command::new("cargo")
-            .args(["run", "--quiet"])
-            .current_dir(temp_dir.path())
-            .output()
-            .context("Failed to run cargo run")?;
-
-        let run_stdout = String::from_utf8_lossy(&run_output.stdout);
-        let run_stderr = String::from_utf8_lossy(&run_output.stderr);
-
-        if run_output.status.success() {
-            return Ok(RustlingsResult {
-                output: format!(
-                    "{}{}Compiling succeeded!\n\nOutput:\n{}{}",
-                    stderr, stdout, run_stderr, run_stdout
-                ),
-                success: true,
-            });
-        }
+    let compiled =
+        compile(temp_dir.path(), &["check"], params).context("Failed to run cargo check")?;
+
+    if !compiled.success {
+        return Ok(compiled.into_result());
+    }
+
+    // If check succeeded, also build and run the code to show output. Only
+    // the binary runs in the jail, not cargo
+    let build_output = run(
+        cargo(temp_dir.path()).args(["build", "--quiet"]),
+        Phase::Compile,
+        &params.limits,
+    )
+    .context("Failed to run cargo build")?;
+
+    let run_output = if build_output.success() {
+        run(
+            Command::new(temp_dir.path().join(BINARY)).current_dir(temp_dir.path()),
+            Phase::Run,
+            &params.limits,
+        )
+        .context("Failed to run the exercise")?
+    } else {
+        build_output
+    };
+
+    if run_output.success() {
+        return Ok(RustlingsResult {
+            output: format!(
+                "{}Compiling succeeded!\n\nOutput:\n{}{}",
+                compiled.output,
+                strip_colors(&run_output.stderr),
+                strip_colors(&run_output.stdout)
+            ),
+            success: true,
+            compiled: true,
+            diagnostics: compiled.diagnostics,
+            limit_exceeded: None,
+        });
+    }
+
+    let mut output = compiled.output;
+    if let Some(limit_exceeded) = run_output.limit_exceeded {
+        push_limit_exceeded(&mut output, &limit_exceeded);
     }
 
     Ok(RustlingsResult {
-        output: format!("{}{}", stderr, stdout),
+        output,
         success: false,
+        compiled: true,
+        diagnostics: compiled.diagnostics,
+        limit_exceeded: run_output.limit_exceeded,
     })
 }
 
+/// Output of the compile step of an exercise
+struct Compiled {
+    output: String,
+    success: bool,
+    diagnostics: Vec<Diagnostic>,
+    /// The test binaries built by `cargo test --no-run`
+    test_executables: Vec<TestExecutable>,
+    limit_exceeded: Option<LimitExceeded>,
+}
+
+impl Compiled {
+    fn into_result(self) -> RustlingsResult {
+        RustlingsResult {
+            output: self.output,
+            success: false,
+            compiled: false,
+            diagnostics: self.diagnostics,
+            limit_exceeded: self.limit_exceeded,
+        }
+    }
+}
+
+/// Runs `cargo <args>` with the compile limits through cargo's JSON messages,
+/// the diagnostics are only kept when they are requested
+fn compile(project_path: &Path, args: &[&str], params: &RustlingsParams) -> Result<Compiled> {
+    let files = std::iter::once(MAIN_FILE)
+        .chain(params.files.keys().map(String::as_str))
+        .collect::<Vec<_>>();
+
+    let build = build(cargo(project_path).args(args), &files, &params.limits)?;
+
+    Ok(Compiled {
+        output: Sanitizer::new(project_path, params.verbose)
+            .sanitize(&without_executable_lines(&build.output)),
+        success: build.messages.success && build.limit_exceeded.is_none(),
+        diagnostics: match params.diagnostics {
+            true => build.messages.diagnostics,
+            false => Vec::new(),
+        },
+        test_executables: build.messages.test_executables,
+        limit_exceeded: build.limit_exceeded,
+    })
+}
+
+/// A cargo command building the exercise into its own target directory
+fn cargo(project_path: &Path) -> Command {
+    let mut command = Command::new("cargo");
+    command
+        .current_dir(project_path)
+        .env("CARGO_TARGET_DIR", project_path.join("target"));
+    command
+}
+
 /// Create a temporary Cargo project for the rustlings exercise
-fn create_rustlings_project(code: &str) -> Result<TempDir> {
+fn create_rustlings_project(code: &str, files: &Files) -> Result<TempDir> {
     let temp_dir = TempDir::new().context("Failed to create temp directory")?;
     let project_path = temp_dir.path();
 
@@ -101,7 +278,13 @@ fn create_rustlings_project(code: &str) -> Result<TempDir> {
     fs::create_dir(project_path.join("src")).context("Failed to create src directory")?;
 
     // Write main.rs with the user code
-    fs::write(project_path.join("src/main.rs"), code).context("Failed to write main.rs")?;
+    fs::write(project_path.join(MAIN_FILE), code).context("Failed to write main.rs")?;
+
+    for (path, content) in files {
+        check_path(path, &[MAIN_FILE])?;
+        write_file(&project_path.join(path), content)
+            .with_context(|| format!("Failed to write {}", path))?;
+    }
 
     // Write Cargo.toml
     let cargo_toml = r#"[package]
@@ -0,0 +1,252 @@
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+/// A compiler diagnostic with its spans mapped onto the submitted files
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Diagnostic {
+    pub level: String,
+    /// Error code or lint name, e.g. `E0308`
+    pub code: Option<String>,
+    pub message: String,
+    pub spans: Vec<Span>,
+    /// Machine applicable fixes suggested by the compiler
+    pub suggestions: Vec<Suggestion>,
+    /// The diagnostic as rustc would print it
+    pub rendered: String,
+}
+
+impl Diagnostic {
+    /// The diagnostic without the source lines rustc rendered, for the ones
+    /// in code the learner can't see
+    pub fn redacted(self) -> Self {
+        let rendered = match &self.code {
+            Some(code) => format!("{}[{}]: {}\n", self.level, code, self.message),
+            None => format!("{}: {}\n", self.level, self.message),
+        };
+
+        Self { rendered, ..self }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Span {
+    /// Path relative to the crate root, e.g. `src/lib.rs`
+    pub file: String,
+    pub line_start: usize,
+    pub column_start: usize,
+    pub line_end: usize,
+    pub column_end: usize,
+    pub is_primary: bool,
+    pub label: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Suggestion {
+    pub message: String,
+    pub span: Span,
+    pub replacement: String,
+}
+
+/// The diagnostic format emitted by rustc with `--error-format=json`
+#[derive(Debug, Deserialize)]
+pub struct RustcDiagnostic {
+    message: String,
+    code: Option<RustcCode>,
+    level: String,
+    spans: Vec<RustcSpan>,
+    children: Vec<RustcDiagnostic>,
+    rendered: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct RustcCode {
+    code: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct RustcSpan {
+    file_name: String,
+    line_start: usize,
+    line_end: usize,
+    column_start: usize,
+    column_end: usize,
+    is_primary: bool,
+    label: Option<String>,
+    suggested_replacement: Option<String>,
+    suggestion_applicability: Option<String>,
+    expansion: Option<Box<RustcExpansion>>,
+}
+
+#[derive(Debug, Deserialize)]
+struct RustcExpansion {
+    span: RustcSpan,
+}
+
+impl RustcDiagnostic {
+    /// Converts the diagnostic, keeping only the spans that point into one of
+    /// the submitted `files` of the crate at `root`. Returns `None` for
+    /// summaries such as `aborting due to 2 previous errors`
+    pub fn into_diagnostic(self, root: &Path, files: &[&str]) -> Option<Diagnostic> {
+        if self.level == "failure-note" || (self.spans.is_empty() && is_summary(&self.message)) {
+            return None;
+        }
+
+        let spans = self
+            .spans
+            .iter()
+            .filter_map(|span| span.to_span(root, files))
+            .collect();
+
+        let suggestions = self
+            .children
+            .iter()
+            .flat_map(|child| {
+                child.spans.iter().filter_map(|span| {
+                    if span.suggestion_applicability.as_deref() != Some("MachineApplicable") {
+                        return None;
+                    }
+
+                    Some(Suggestion {
+                        message: child.message.clone(),
+                        span: span.to_span(root, files)?,
+                        replacement: span.suggested_replacement.clone()?,
+                    })
+                })
+            })
+            .collect();
+
+        Some(Diagnostic {
+            level: self.level,
+            code: self.code.map(|code| code.code),
+            message: self.message,
+            spans,
+            suggestions,
+            rendered: self.rendered.unwrap_or_default(),
+        })
+    }
+}
+
+impl RustcSpan {
+    /// Spans inside macros are mapped to the place the macro was invoked
+    fn to_span(&self, root: &Path, files: &[&str]) -> Option<Span> {
+        match submission_path(&self.file_name, root, files) {
+            Some(file) => Some(Span {
+                file: file.to_string(),
+                line_start: self.line_start,
+                column_start: self.column_start,
+                line_end: self.line_end,
+                column_end: self.column_end,
+                is_primary: self.is_primary,
+                label: self.label.clone(),
+            }),
+            None => {
+                let mut span = self.expansion.as_ref()?.span.to_span(root, files)?;
+                span.is_primary = self.is_primary;
+                span.label = self.label.clone();
+                Some(span)
+            }
+        }
+    }
+}
+
+/// Finds which submitted file a compiler path refers to. Cargo reports the
+/// paths of the crate relative to its workspace root, `root`, and the ones of
+/// the dependencies and the standard library as absolute paths
+pub fn submission_path<'a>(file_name: &str, root: &Path, files: &[&'a str]) -> Option<&'a str> {
+    let path = Path::new(file_name);
+    let path = path.strip_prefix(root).unwrap_or(path);
+
+    if path.is_absolute() {
+        return None;
+    }
+
+    files.iter().find(|file| Path::new(file) == path).copied()
+}
+
+fn is_summary(message: &str) -> bool {
+    message.starts_with("aborting due to")
+        || message.ends_with("warning emitted")
+        || message.ends_with("warnings emitted")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ROOT: &str = "/app/challenges/.run-0";
+    const FILES: [&str; 2] = ["src/lib.rs", "tests/tests.rs"];
+
+    fn parse(json: &str) -> Option<Diagnostic> {
+        serde_json::from_str::<RustcDiagnostic>(json)
+            .unwrap()
+            .into_diagnostic(Path::new(ROOT), &FILES)
+    }
+
+    #[test]
+    fn test_maps_spans_to_submission() {
+        let json = r#"{"$message_type":"diagnostic","message":"unused variable: `y`","code":{"code":"unused_variables","explanation":null},"level":"warning","spans":[{"file_name":"src/lib.rs","byte_start":24,"byte_end":25,"line_start":1,"line_end":1,"column_start":25,"column_end":26,"is_primary":true,"text":[],"label":null,"suggested_replacement":null,"suggestion_applicability":null,"expansion":null}],"children":[{"message":"if this is intentional, prefix it with an underscore","code":null,"level":"help","spans":[{"file_name":"/app/challenges/.run-0/src/lib.rs","byte_start":24,"byte_end":25,"line_start":1,"line_end":1,"column_start":25,"column_end":26,"is_primary":true,"text":[],"label":null,"suggested_replacement":"_y","suggestion_applicability":"MachineApplicable","expansion":null}],"children":[],"rendered":null}],"rendered":"warning: unused variable: `y`\n"}"#;
+
+        let span = Span {
+            file: "src/lib.rs".to_string(),
+            line_start: 1,
+            column_start: 25,
+            line_end: 1,
+            column_end: 26,
+            is_primary: true,
+            label: None,
+        };
+
+        assert_eq!(
+            parse(json),
+            Some(Diagnostic {
+                level: "warning".to_string(),
+                code: Some("unused_variables".to_string()),
+                message: "unused variable: `y`".to_string(),
+                spans: vec![span.clone()],
+                suggestions: vec![Suggestion {
+                    message: "if this is intentional, prefix it with an underscore".to_string(),
+                    span,
+                    replacement: "_y".to_string(),
+                }],
+                rendered: "warning: unused variable: `y`\n".to_string(),
+            })
+        );
+
+        // Files of the dependencies or outside the crate with the same names
+        for file_name in [
+            "/root/.cargo/registry/src/index.crates.io-6f17d22bba15001f/syn-2.0.96/src/lib.rs",
+            "../playground/src/lib.rs",
+            "challenges/playground/src/lib.rs",
+        ] {
+            assert_eq!(submission_path(file_name, Path::new(ROOT), &FILES), None);
+        }
+    }
+
+    #[test]
+    fn test_maps_macro_spans_to_call_site() {
+        let json = r#"{"message":"mismatched types","code":{"code":"E0308"},"level":"error","spans":[{"file_name":"/rustc/abc/library/alloc/src/macros.rs","line_start":40,"line_end":40,"column_start":9,"column_end":20,"is_primary":true,"label":"expected `u8`","suggested_replacement":null,"suggestion_applicability":null,"expansion":{"span":{"file_name":"src/lib.rs","line_start":3,"line_end":3,"column_start":14,"column_end":18,"is_primary":false,"label":null,"suggested_replacement":null,"suggestion_applicability":null,"expansion":null}}}],"children":[],"rendered":"error[E0308]: mismatched types\n"}"#;
+
+        let diagnostic = parse(json).unwrap();
+
+        assert_eq!(
+            diagnostic.spans,
+            vec![Span {
+                file: "src/lib.rs".to_string(),
+                line_start: 3,
+                column_start: 14,
+                line_end: 3,
+                column_end: 18,
+                is_primary: true,
+                label: Some("expected `u8`".to_string()),
+            }]
+        );
+    }
+
+    #[test]
+    fn test_skips_summaries() {
+        let json = r#"{"message":"aborting due to 2 previous errors","code":null,"level":"error","spans":[],"children":[],"rendered":"error: aborting due to 2 previous errors\n"}"#;
+
+        assert_eq!(parse(json), None);
+    }
+}
@@ -0,0 +1,206 @@
+/// Lines of context around the changes of a hunk
+const CONTEXT: usize = 3;
+
+/// Size of the LCS table of the lines that differ, past it they're all shown
+/// as removed and added
+const MAX_TABLE_CELLS: usize = 4 * 1024 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Edit {
+    Same,
+    Removed,
+    Added,
+}
+
+/// Unified diff of two texts by lines, empty when they're the same. Lines
+/// are compared with their line endings so a missing newline at the end is
+/// a change too
+pub fn unified_diff(old: &str, new: &str, old_name: &str, new_name: &str) -> String {
+    let old = old.split_inclusive('\n').collect::<Vec<_>>();
+    let new = new.split_inclusive('\n').collect::<Vec<_>>();
+    let edits = edits(&old, &new);
+
+    if edits.iter().all(|edit| *edit == Edit::Same) {
+        return String::new();
+    }
+
+    let mut diff = format!("--- {}\n+++ {}\n", old_name, new_name);
+
+    // Position of each edit in the old and new lines
+    let mut positions = Vec::with_capacity(edits.len());
+    let (mut old_line, mut new_line) = (0, 0);
+    for edit in &edits {
+        positions.push((old_line, new_line));
+        match edit {
+            Edit::Same => {
+                old_line += 1;
+                new_line += 1;
+            }
+            Edit::Removed => old_line += 1,
+            Edit::Added => new_line += 1,
+        }
+    }
+
+    for (start, end) in hunks(&edits) {
+        let (old_start, new_start) = positions[start];
+        let old_len = edits[start..end]
+            .iter()
+            .filter(|edit| **edit != Edit::Added)
+            .count();
+        let new_len = edits[start..end]
+            .iter()
+            .filter(|edit| **edit != Edit::Removed)
+            .count();
+
+        diff.push_str(&format!(
+            "@@ -{} +{} @@\n",
+            range(old_start, old_len),
+            range(new_start, new_len)
+        ));
+
+        for (edit, (old_line, new_line)) in edits[start..end].iter().zip(&positions[start..end]) {
+            let (prefix, line) = match edit {
+                Edit::Same => (' ', old[*old_line]),
+                Edit::Removed => ('-', old[*old_line]),
+                Edit::Added => ('+', new[*new_line]),
+            };
+
+            diff.push(prefix);
+            diff.push_str(line);
+            if !line.ends_with('\n') {
+                diff.push_str("\n\\ No newline at end of file\n");
+            }
+        }
+    }
+
+    diff
+}
+
+/// `start,len` with 1-based lines, an empty range starts at the line before
+fn range(start: usize, len: usize) -> String {
+    match len {
+        0 => format!("{},0", start),
+        1 => format!("{}", start + 1),
+        len => format!("{},{}", start + 1, len),
+    }
+}
+
+/// The edits turning `old` into `new`, from their longest common subsequence.
+/// The common start and end are left out of the table, which is skipped when
+/// it would be larger than [`MAX_TABLE_CELLS`]
+fn edits(old: &[&str], new: &[&str]) -> Vec<Edit> {
+    let prefix = old
+        .iter()
+        .zip(new)
+        .take_while(|(old, new)| old == new)
+        .count();
+    let suffix = old[prefix..]
+        .iter()
+        .rev()
+        .zip(new[prefix..].iter().rev())
+        .take_while(|(old, new)| old == new)
+        .count();
+
+    let old_middle = &old[prefix..old.len() - suffix];
+    let new_middle = &new[prefix..new.len() - suffix];
+    let (n, m) = (old_middle.len(), new_middle.len());
+
+    let mut edits = vec![Edit::Same; prefix];
+    if (n + 1).saturating_mul(m + 1) > MAX_TABLE_CELLS {
+        edits.extend(vec![Edit::Removed; n]);
+        edits.extend(vec![Edit::Added; m]);
+        edits.extend(vec![Edit::Same; suffix]);
+        return edits;
+    }
+
+    // lcs[i][j] is the length of the LCS of old_middle[i..] and new_middle[j..]
+    let mut lcs = vec![vec![0u32; m + 1]; n + 1];
+    for i in (0..n).rev() {
+        for j in (0..m).rev() {
+            lcs[i][j] = if old_middle[i] == new_middle[j] {
+                lcs[i + 1][j + 1] + 1
+            } else {
+                lcs[i + 1][j].max(lcs[i][j + 1])
+            };
+        }
+    }
+
+    let (mut i, mut j) = (0, 0);
+    while i < n || j < m {
+        if i < n && j < m && old_middle[i] == new_middle[j] {
+            edits.push(Edit::Same);
+            i += 1;
+            j += 1;
+        } else if j == m || (i < n && lcs[i + 1][j] >= lcs[i][j + 1]) {
+            edits.push(Edit::Removed);
+            i += 1;
+        } else {
+            edits.push(Edit::Added);
+            j += 1;
+        }
+    }
+    edits.extend(vec![Edit::Same; suffix]);
+
+    edits
+}
+
+/// Ranges of the edits shown in each hunk, changes closer than twice the
+/// context share a hunk
+fn hunks(edits: &[Edit]) -> Vec<(usize, usize)> {
+    let mut hunks: Vec<(usize, usize)> = Vec::new();
+
+    for (index, _) in edits
+        .iter()
+        .enumerate()
+        .filter(|(_, edit)| **edit != Edit::Same)
+    {
+        let start = index.saturating_sub(CONTEXT);
+        let end = (index + 1 + CONTEXT).min(edits.len());
+
+        match hunks.last_mut() {
+            Some(last) if start <= last.1 => last.1 = end,
+            _ => hunks.push((start, end)),
+        }
+    }
+
+    hunks
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_unified_diff() {
+        let old = "fn main(){\nlet x=1;\n}\n\n// a\n// b\n// c\n// d\n// e\n// f\n";
+        let new = "fn main() {\n    let x = 1;\n}\n\n// a\n// b\n// c\n// d\n// e\n// f\n";
+
+        assert_eq!(
+            unified_diff(old, new, "original", "formatted"),
+            "--- original\n+++ formatted\n\
+            @@ -1,5 +1,5 @@\n\
+            -fn main(){\n\
+            -let x=1;\n\
+            +fn main() {\n\
+            +    let x = 1;\n \
+            }\n \n \
+            // a\n"
+        );
+        assert_eq!(unified_diff(new, new, "original", "formatted"), "");
+        assert_eq!(
+            unified_diff("a\n", "a\nb\n", "original", "formatted"),
+            "--- original\n+++ formatted\n@@ -1 +1,2 @@\n a\n+b\n"
+        );
+        assert_eq!(
+            unified_diff("a\nb", "a\nb\n", "original", "formatted"),
+            "--- original\n+++ formatted\n@@ -1,2 +1,2 @@\n a\n-b\n\\ No newline at end of file\n+b\n"
+        );
+
+        let old = "a\n".repeat(3000);
+        let new = "b\n".repeat(3000);
+        let diff = unified_diff(&old, &new, "original", "formatted");
+        assert!(diff.starts_with("--- original\n+++ formatted\n@@ -1,3000 +1,3000 @@\n-a\n"));
+        assert_eq!(diff.lines().filter(|line| *line == "-a").count(), 3000);
+        assert_eq!(diff.lines().filter(|line| *line == "+b").count(), 3000);
+    }
+}
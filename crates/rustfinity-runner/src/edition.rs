@@ -0,0 +1,31 @@
+use serde::Deserialize;
+
+/// Rust edition of the code
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+pub enum Edition {
+    #[cfg_attr(feature = "cli", value(name = "2015"))]
+    #[serde(rename = "2015")]
+    E2015,
+    #[cfg_attr(feature = "cli", value(name = "2018"))]
+    #[serde(rename = "2018")]
+    E2018,
+    #[default]
+    #[cfg_attr(feature = "cli", value(name = "2021"))]
+    #[serde(rename = "2021")]
+    E2021,
+    #[cfg_attr(feature = "cli", value(name = "2024"))]
+    #[serde(rename = "2024")]
+    E2024,
+}
+
+impl Edition {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Edition::E2015 => "2015",
+            Edition::E2018 => "2018",
+            Edition::E2021 => "2021",
+            Edition::E2024 => "2024",
+        }
+    }
+}
@@ -0,0 +1,180 @@
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use crate::{manifest::PolicyViolation, process::LimitExceeded, rlimit::Resource};
+
+/// How a job that ran ended, with the runner's exit code for each
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Outcome {
+    /// The code compiled and everything passed, exits with 0
+    Passed,
+    /// A test failed, a denied lint fired or the program exited with an
+    /// error, exits with 1
+    Failed,
+    /// The code doesn't compile, or rustfmt couldn't parse it, exits with 10
+    CompileError,
+    /// The compilation or the run timed out or used up its CPU time, exits
+    /// with 11
+    TimedOut,
+    /// The code ran out of memory, exits with 12
+    OutOfMemory,
+    /// The code hit another limit, e.g. the output or the processes, exits
+    /// with 13
+    LimitExceeded,
+}
+
+impl Outcome {
+    pub fn exit_code(&self) -> u8 {
+        match self {
+            Outcome::Passed => 0,
+            Outcome::Failed => 1,
+            Outcome::CompileError => 10,
+            Outcome::TimedOut => 11,
+            Outcome::OutOfMemory => 12,
+            Outcome::LimitExceeded => 13,
+        }
+    }
+
+    pub fn from_limit(limit_exceeded: &LimitExceeded) -> Self {
+        match limit_exceeded {
+            LimitExceeded::TimedOut { .. }
+            | LimitExceeded::ResourceLimit {
+                resource: Resource::CpuTime,
+                ..
+            } => Outcome::TimedOut,
+            LimitExceeded::ResourceLimit {
+                resource: Resource::Memory,
+                ..
+            } => Outcome::OutOfMemory,
+            LimitExceeded::OutputTruncated { .. } | LimitExceeded::ResourceLimit { .. } => {
+                Outcome::LimitExceeded
+            }
+        }
+    }
+}
+
+/// A job the runner can't run as it is, e.g. invalid base64. An error with
+/// it in its chain, as the error or a context, is a
+/// [`RunnerError::InvalidJob`]
+#[derive(Debug)]
+pub struct InvalidJob(pub String);
+
+impl fmt::Display for InvalidJob {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for InvalidJob {}
+
+/// Why the runner couldn't run a job
+#[derive(Debug)]
+pub enum RunnerError {
+    /// The caller's mistake, e.g. invalid arguments or a Cargo.toml the
+    /// policy doesn't allow. Running it again fails the same way. Exits with
+    /// 64
+    InvalidJob(anyhow::Error),
+    /// The runner or the host failed, e.g. cargo couldn't be spawned. The job
+    /// can be retried. Exits with 70
+    Internal(anyhow::Error),
+}
+
+impl RunnerError {
+    pub fn kind(&self) -> &'static str {
+        match self {
+            RunnerError::InvalidJob(_) => "invalid_job",
+            RunnerError::Internal(_) => "internal",
+        }
+    }
+
+    pub fn retryable(&self) -> bool {
+        matches!(self, RunnerError::Internal(_))
+    }
+
+    pub fn exit_code(&self) -> u8 {
+        match self {
+            RunnerError::InvalidJob(_) => 64,
+            RunnerError::Internal(_) => 70,
+        }
+    }
+
+    /// The message with its causes
+    pub fn message(&self) -> String {
+        match self {
+            RunnerError::InvalidJob(e) | RunnerError::Internal(e) => format!("{:#}", e),
+        }
+    }
+
+    /// The error as JSON, what `--format json` prints when a job fails
+    pub fn to_json(&self) -> Value {
+        json!({
+            "error": {
+                "kind": self.kind(),
+                "message": self.message(),
+                "retryable": self.retryable(),
+            }
+        })
+    }
+}
+
+impl From<anyhow::Error> for RunnerError {
+    fn from(e: anyhow::Error) -> Self {
+        if e.downcast_ref::<InvalidJob>().is_some() || e.downcast_ref::<PolicyViolation>().is_some()
+        {
+            RunnerError::InvalidJob(e)
+        } else {
+            RunnerError::Internal(e)
+        }
+    }
+}
+
+impl fmt::Display for RunnerError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RunnerError::InvalidJob(e) | RunnerError::Internal(e) => e.fmt(f),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::{anyhow, Context};
+
+    use super::*;
+    use crate::process::Phase;
+
+    #[test]
+    fn test_classifies_errors() {
+        let invalid = Err::<(), _>(anyhow!("Invalid base64"))
+            .context(InvalidJob("Failed to decode --code".to_string()))
+            .context("Failed to read the job")
+            .unwrap_err();
+        let error = RunnerError::from(invalid);
+
+        assert_eq!(error.exit_code(), 64);
+        assert_eq!(
+            error.to_json(),
+            json!({
+                "error": {
+                    "kind": "invalid_job",
+                    "message": "Failed to read the job: Failed to decode --code: Invalid base64",
+                    "retryable": false,
+                }
+            })
+        );
+
+        let internal = RunnerError::from(anyhow!("Failed to run cargo"));
+        assert_eq!(internal.kind(), "internal");
+        assert!(internal.retryable());
+
+        let oom = LimitExceeded::ResourceLimit {
+            phase: Phase::Run,
+            resource: Resource::Memory,
+            limit: 1024,
+        };
+        assert_eq!(Outcome::from_limit(&oom).exit_code(), 12);
+    }
+}
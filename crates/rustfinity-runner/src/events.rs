@@ -0,0 +1,171 @@
+use std::{fmt, sync::Arc};
+
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::{
+    diagnostics::Diagnostic,
+    error::Outcome,
+    libtest::{parse_test_event, TestEvent},
+    verdict::{Benchmark, Comparison, TestStatus},
+};
+
+/// Progress of a job, streamed as newline-delimited JSON with
+/// `--format ndjson`
+#[derive(Debug, Serialize)]
+#[serde(tag = "event", rename_all = "kebab-case")]
+pub enum Event<'a> {
+    /// Cargo started compiling a crate, a dependency or the submission
+    CompilingCrate {
+        name: &'a str,
+        version: &'a str,
+    },
+    BuildFinished {
+        success: bool,
+        duration_ms: f64,
+        diagnostics: &'a [Diagnostic],
+    },
+    /// `hidden` tests of the test events only have their name and status
+    TestStarted {
+        name: &'a str,
+        hidden: bool,
+    },
+    TestPassed {
+        name: &'a str,
+        hidden: bool,
+        duration_ms: Option<f64>,
+    },
+    TestFailed {
+        name: &'a str,
+        hidden: bool,
+        /// Panic message of the test
+        message: Option<&'a str>,
+        /// Everything the test printed
+        output: Option<&'a str>,
+        duration_ms: Option<f64>,
+    },
+    TestIgnored {
+        name: &'a str,
+        hidden: bool,
+    },
+    BenchmarkResult(&'a Benchmark),
+    ComparisonResult(&'a Comparison),
+    /// The last event, `result` is what `--format json` returns
+    Done {
+        success: bool,
+        outcome: Outcome,
+        result: &'a Value,
+    },
+    /// The last event when the runner couldn't run the job, with the
+    /// `error` of [`RunnerError::to_json`](crate::error::RunnerError::to_json)
+    Error(&'a Value),
+}
+
+impl Event<'_> {
+    /// The event as one line of JSON, without the newline
+    pub fn to_line(&self) -> String {
+        serde_json::to_string(self).expect("events serialize to JSON")
+    }
+}
+
+/// Where the events of a job go, called from the threads running its
+/// commands. The default drops them
+#[derive(Clone, Default)]
+pub struct Events(Option<Arc<Sink>>);
+
+type Sink = dyn Fn(&Event) + Send + Sync;
+
+impl Events {
+    pub fn new(sink: impl Fn(&Event) + Send + Sync + 'static) -> Self {
+        Self(Some(Arc::new(sink)))
+    }
+
+    pub fn emit(&self, event: &Event) {
+        if let Some(sink) = &self.0 {
+            sink(event);
+        }
+    }
+
+    /// Emits [`Event::CompilingCrate`] for a `Compiling` line of cargo's
+    /// stderr, e.g. `   Compiling syn v2.0.90`
+    pub fn cargo_line(&self, line: &str) {
+        let Some(rest) = line.trim_start().strip_prefix("Compiling ") else {
+            return;
+        };
+        let mut words = rest.split_whitespace();
+
+        if let (Some(name), Some(version)) = (words.next(), words.next()) {
+            self.emit(&Event::CompilingCrate {
+                name,
+                version: version.trim_start_matches('v'),
+            });
+        }
+    }
+
+    /// Emits the event of a line of a test binary run with libtest's JSON
+    /// output, when it's a test starting or finishing. The results of
+    /// `hidden` tests are redacted
+    pub fn test_line(&self, line: &str, hidden: bool) {
+        let event = match parse_test_event(line) {
+            Some(TestEvent::Finished(test, _)) if hidden => {
+                TestEvent::Finished(test.redacted(), None)
+            }
+            Some(event) => event,
+            None => return,
+        };
+
+        match &event {
+            TestEvent::Started(name) => self.emit(&Event::TestStarted { name, hidden }),
+            TestEvent::Finished(test, output) => self.emit(&match test.status {
+                TestStatus::Passed => Event::TestPassed {
+                    name: &test.name,
+                    hidden,
+                    duration_ms: test.duration_ms,
+                },
+                TestStatus::Failed => Event::TestFailed {
+                    name: &test.name,
+                    hidden,
+                    message: test.message.as_deref(),
+                    output: output.as_deref(),
+                    duration_ms: test.duration_ms,
+                },
+                TestStatus::Ignored => Event::TestIgnored {
+                    name: &test.name,
+                    hidden,
+                },
+            }),
+        }
+    }
+}
+
+impl fmt::Debug for Events {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("Events").field(&self.0.is_some()).finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use super::*;
+
+    #[test]
+    fn test_emits_compiling_crates() {
+        let lines = Arc::new(Mutex::new(Vec::new()));
+        let emitted = Arc::clone(&lines);
+        let events = Events::new(move |event| emitted.lock().unwrap().push(event.to_line()));
+
+        events.cargo_line("   Compiling syn v2.0.90");
+        events.cargo_line("   Compiling playground v0.1.0 (/app/challenges/.run-0)");
+        events.cargo_line("    Finished `test` profile [unoptimized + debuginfo] target(s)");
+
+        assert_eq!(
+            *lines.lock().unwrap(),
+            [
+                r#"{"event":"compiling-crate","name":"syn","version":"2.0.90"}"#,
+                r#"{"event":"compiling-crate","name":"playground","version":"0.1.0"}"#,
+            ]
+        );
+    }
+}
@@ -0,0 +1,90 @@
+use std::{
+    collections::BTreeMap,
+    path::{Component, Path},
+};
+
+use anyhow::bail;
+
+use crate::error::InvalidJob;
+
+/// Files of a submission by their path relative to the crate root, on top
+/// of the ones the command always writes
+pub type Files = BTreeMap<String, String>;
+
+/// Files the runner writes itself or that change how cargo builds the crate
+const RESERVED: [&str; 4] = [
+    "Cargo.toml",
+    "Cargo.lock",
+    "rust-toolchain",
+    "rust-toolchain.toml",
+];
+
+/// Directories of the crate a submission can't write to
+const RESERVED_DIRECTORIES: [&str; 2] = ["target", ".cargo"];
+
+/// Checks that a submitted path is a file in the crate, `written` are the
+/// files the command writes itself
+pub fn check_path(path: &str, written: &[&str]) -> anyhow::Result<()> {
+    let components = Path::new(path).components().collect::<Vec<_>>();
+
+    if components.is_empty()
+        || !components
+            .iter()
+            .all(|component| matches!(component, Component::Normal(_)))
+    {
+        bail!(InvalidJob(format!(
+            "Invalid file path `{}`, it must be relative to the crate root and can't contain `..`",
+            path
+        )));
+    }
+
+    // Paths are compared by their components, `src//lib.rs` is `src/lib.rs`
+    if RESERVED
+        .iter()
+        .chain(written)
+        .any(|reserved| Path::new(reserved) == Path::new(path))
+    {
+        bail!(InvalidJob(format!(
+            "Invalid file path `{}`, the runner writes this file",
+            path
+        )));
+    }
+
+    if RESERVED_DIRECTORIES
+        .iter()
+        .any(|directory| components[0] == Component::Normal(directory.as_ref()))
+    {
+        bail!(InvalidJob(format!(
+            "Invalid file path `{}`, it can't be in `{}`",
+            path,
+            components[0].as_os_str().display()
+        )));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check_path() {
+        let written = ["src/lib.rs"];
+
+        assert!(check_path("src/parser.rs", &written).is_ok());
+        assert!(check_path("tests/other.rs", &written).is_ok());
+        assert!(check_path("examples/demo.rs", &written).is_ok());
+
+        assert!(check_path("", &written).is_err());
+        assert!(check_path("/etc/passwd", &written).is_err());
+        assert!(check_path("../escape.rs", &written).is_err());
+        assert!(check_path("src/../../escape.rs", &written).is_err());
+        assert!(check_path("./src/other.rs", &written).is_err());
+        assert!(check_path("src/lib.rs", &written).is_err());
+        assert!(check_path("src//lib.rs", &written).is_err());
+        assert!(check_path("Cargo.toml", &written).is_err());
+        assert!(check_path(".cargo/config.toml", &written).is_err());
+        assert!(check_path("target/debug/playground", &written).is_err());
+    }
+}
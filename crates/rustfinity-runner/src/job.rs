@@ -0,0 +1,486 @@
+use std::collections::BTreeMap;
+
+use serde::{de, Deserialize, Deserializer, Serialize};
+use serde_json::Value;
+
+use crate::{
+    benchmark::BenchmarkOptions,
+    cache::{job_key, ResultCache},
+    cli::OutputFormat,
+    commands::{
+        fmt::{run_fmt, FmtParams},
+        lint::{run_lint, LintParams},
+        playground::{run_code_in_playground, run_playground_report, Mode, PlaygroundParams},
+        run_tests::{run_tests, run_tests_verdict, RunTestsParams},
+        rustlings::{run_rustlings, RustlingsMode, RustlingsParams, RustlingsResult},
+        TextOutput,
+    },
+    edition::Edition,
+    error::Outcome,
+    events::Events,
+    files::Files,
+    manifest::ManifestPolicy,
+    memory::HeapProfiler,
+    process::Limits,
+    scoring::Scoring,
+    utils::{from_base64, to_utf8},
+};
+
+/// A run of one of the commands, from the command line, a job manifest or
+/// the job server. In JSON the files are base64 encoded, like the arguments.
+/// `files` are the extra files of the crate by their path in the crate
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "command", rename_all = "kebab-case")]
+pub enum Job {
+    Test {
+        #[serde(deserialize_with = "base64")]
+        code: String,
+        #[serde(deserialize_with = "base64")]
+        tests: String,
+        #[serde(deserialize_with = "base64")]
+        cargo_toml: String,
+        /// Tests whose results only have the test's name and status
+        #[serde(default, deserialize_with = "optional_base64")]
+        hidden_tests: Option<String>,
+        /// The TOML with the weights and concept groups of the tests
+        #[serde(default, deserialize_with = "optional_base64")]
+        scoring: Option<String>,
+        #[serde(default, deserialize_with = "base64_files")]
+        files: Files,
+        #[serde(flatten)]
+        benchmark: BenchmarkOptions,
+        /// The reference solution's `src/lib.rs`
+        #[serde(default, deserialize_with = "optional_base64")]
+        reference: Option<String>,
+        /// Keeps cargo's build output in the text output
+        #[serde(default)]
+        verbose: bool,
+        /// Runs the job even when the result cache has its verdict
+        #[serde(default)]
+        no_cache: bool,
+        #[serde(default)]
+        format: OutputFormat,
+    },
+    Lint {
+        #[serde(deserialize_with = "base64")]
+        code: String,
+        #[serde(deserialize_with = "base64")]
+        cargo_toml: String,
+        #[serde(default, deserialize_with = "base64_files")]
+        files: Files,
+        #[serde(default)]
+        deny: Vec<String>,
+        #[serde(default)]
+        format: OutputFormat,
+    },
+    Fmt {
+        #[serde(deserialize_with = "base64")]
+        code: String,
+        #[serde(default)]
+        edition: Edition,
+        #[serde(default)]
+        config: Vec<String>,
+        #[serde(default)]
+        format: OutputFormat,
+    },
+    Playground {
+        #[serde(deserialize_with = "base64")]
+        code: String,
+        #[serde(default, deserialize_with = "base64_files")]
+        files: Files,
+        /// Input of the program
+        #[serde(default, deserialize_with = "base64_bytes")]
+        stdin: Vec<u8>,
+        #[serde(default)]
+        args: Vec<String>,
+        #[serde(default)]
+        env: BTreeMap<String, String>,
+        #[serde(default)]
+        mode: Mode,
+        #[serde(default)]
+        release: bool,
+        #[serde(default)]
+        edition: Option<Edition>,
+        #[serde(default)]
+        verbose: bool,
+        #[serde(default)]
+        format: OutputFormat,
+    },
+    RustlingsTest {
+        #[serde(deserialize_with = "base64")]
+        code: String,
+        #[serde(default, deserialize_with = "base64_files")]
+        files: Files,
+        #[serde(default)]
+        verbose: bool,
+        #[serde(default)]
+        format: OutputFormat,
+    },
+    RustlingsCheck {
+        #[serde(deserialize_with = "base64")]
+        code: String,
+        #[serde(default, deserialize_with = "base64_files")]
+        files: Files,
+        #[serde(default)]
+        verbose: bool,
+        #[serde(default)]
+        format: OutputFormat,
+    },
+}
+
+fn base64<'de, D: Deserializer<'de>>(deserializer: D) -> Result<String, D::Error> {
+    let base64 = String::deserialize(deserializer)?;
+    to_utf8(&base64).map_err(|e| de::Error::custom(format!("invalid base64: {}", e)))
+}
+
+fn base64_bytes<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+    let base64 = String::deserialize(deserializer)?;
+    from_base64(&base64).map_err(|e| de::Error::custom(format!("invalid base64: {}", e)))
+}
+
+fn optional_base64<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<String>, D::Error> {
+    Option::<String>::deserialize(deserializer)?
+        .map(|base64| {
+            to_utf8(&base64).map_err(|e| de::Error::custom(format!("invalid base64: {}", e)))
+        })
+        .transpose()
+}
+
+fn base64_files<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Files, D::Error> {
+    Files::deserialize(deserializer)?
+        .into_iter()
+        .map(|(path, base64)| match to_utf8(&base64) {
+            Ok(content) => Ok((path, content)),
+            Err(e) => Err(de::Error::custom(format!(
+                "invalid base64 in {}: {}",
+                path, e
+            ))),
+        })
+        .collect()
+}
+
+impl Job {
+    /// Name of the command, as on the command line
+    pub fn command(&self) -> &'static str {
+        match self {
+            Job::Test { .. } => "test",
+            Job::Lint { .. } => "lint",
+            Job::Fmt { .. } => "fmt",
+            Job::Playground { .. } => "playground",
+            Job::RustlingsTest { .. } => "rustlings-test",
+            Job::RustlingsCheck { .. } => "rustlings-check",
+        }
+    }
+
+    pub fn format(&self) -> OutputFormat {
+        match self {
+            Job::Test { format, .. }
+            | Job::Lint { format, .. }
+            | Job::Fmt { format, .. }
+            | Job::Playground { format, .. }
+            | Job::RustlingsTest { format, .. }
+            | Job::RustlingsCheck { format, .. } => *format,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct JobOutput {
+    /// The text output, or the JSON result with `--format json`
+    pub result: Value,
+    /// How the job ended, decides the exit code
+    pub outcome: Outcome,
+}
+
+impl JobOutput {
+    fn text(output: String, outcome: Outcome) -> Self {
+        Self {
+            result: Value::String(output),
+            outcome,
+        }
+    }
+
+    fn json(result: impl Serialize, outcome: Outcome) -> anyhow::Result<Self> {
+        Ok(Self {
+            result: serde_json::to_value(result)?,
+            outcome,
+        })
+    }
+
+    /// Whether the command exits successfully
+    pub fn success(&self) -> bool {
+        self.outcome == Outcome::Passed
+    }
+}
+
+/// Settings of the runner that apply to every job
+#[derive(Debug, Clone, Default)]
+pub struct JobConfig {
+    pub limits: Limits,
+    /// Checked against the Cargo.toml of `test` jobs
+    pub manifest_policy: ManifestPolicy,
+    /// Measures the heap usage in the benchmarks of `test` jobs
+    pub heap_profiler: HeapProfiler,
+    /// Receives the progress of `test` jobs, unless they use the text format
+    pub events: Events,
+    /// Verdicts of earlier `test` jobs, `None` doesn't cache them
+    pub cache: Option<ResultCache>,
+}
+
+pub async fn run_job(job: Job, config: JobConfig) -> anyhow::Result<JobOutput> {
+    let cache_key = cache_key(&job, &config);
+    if let (Some(cache), Some(key)) = (&config.cache, &cache_key) {
+        if let Some(mut output) = cache
+            .get(key)
+            .and_then(|entry| serde_json::from_value::<JobOutput>(entry).ok())
+        {
+            // The text output is the same as when it ran
+            if let Some(result) = output.result.as_object_mut() {
+                result.insert("cached".to_string(), Value::Bool(true));
+            }
+            return Ok(output);
+        }
+    }
+
+    let JobConfig {
+        limits,
+        manifest_policy,
+        heap_profiler,
+        events,
+        cache,
+    } = config;
+
+    match job {
+        Job::Test {
+            code,
+            tests,
+            cargo_toml,
+            hidden_tests,
+            scoring,
+            files,
+            benchmark,
+            reference,
+            verbose,
+            format,
+            ..
+        } => {
+            let scoring = match scoring {
+                Some(scoring) => Scoring::parse(&scoring)?,
+                None => Scoring::default(),
+            };
+
+            let params = RunTestsParams::new(code, tests, cargo_toml)
+                .with_hidden_tests(hidden_tests)
+                .with_scoring(scoring)
+                .with_files(files)
+                .with_benchmark(benchmark)
+                .with_reference(reference)
+                .with_manifest_policy(manifest_policy)
+                .with_heap_profiler(heap_profiler)
+                .with_limits(limits)
+                .with_verbose(verbose)
+                .with_events(events);
+
+            let (output, limit_exceeded) = match format {
+                OutputFormat::Text => {
+                    let TextOutput { output, outcome } = run_tests(&params).await?;
+                    let limit_exceeded = matches!(
+                        outcome,
+                        Outcome::TimedOut | Outcome::OutOfMemory | Outcome::LimitExceeded
+                    );
+                    (JobOutput::text(output, outcome), limit_exceeded)
+                }
+                OutputFormat::Json | OutputFormat::Ndjson => {
+                    let verdict = run_tests_verdict(&params).await?;
+                    let output = JobOutput::json(&verdict, verdict.outcome())?;
+                    (output, verdict.limit_exceeded.is_some())
+                }
+            };
+
+            // A limit can be hit because the host was busy
+            if let (Some(cache), Some(key), false) = (cache, cache_key, limit_exceeded) {
+                if let Err(e) = serde_json::to_value(&output)
+                    .map_err(anyhow::Error::from)
+                    .and_then(|entry| cache.put(&key, &entry))
+                {
+                    eprintln!("Warning: failed to cache the verdict: {:#}", e);
+                }
+            }
+
+            Ok(output)
+        }
+
+        Job::Lint {
+            code,
+            cargo_toml,
+            files,
+            deny,
+            format,
+        } => {
+            let params = LintParams::new(code, cargo_toml)
+                .with_files(files)
+                .with_deny(deny)
+                .with_manifest_policy(manifest_policy)
+                .with_limits(limits);
+            let report = run_lint(&params).await?;
+
+            // Fails when a denied lint fired, like a failed rustlings exercise
+            match format {
+                OutputFormat::Text => Ok(JobOutput::text(report.text(), report.outcome())),
+                OutputFormat::Json | OutputFormat::Ndjson => {
+                    JobOutput::json(&report, report.outcome())
+                }
+            }
+        }
+
+        Job::Fmt {
+            code,
+            edition,
+            config,
+            format,
+        } => {
+            let params = FmtParams::new(code)
+                .with_edition(edition)
+                .with_config(config)
+                .with_limits(limits);
+            let result = run_fmt(&params).await?;
+
+            match format {
+                OutputFormat::Text => Ok(JobOutput::text(result.text(), result.outcome())),
+                OutputFormat::Json | OutputFormat::Ndjson => {
+                    JobOutput::json(&result, result.outcome())
+                }
+            }
+        }
+
+        Job::Playground {
+            code,
+            files,
+            stdin,
+            args,
+            env,
+            mode,
+            release,
+            edition,
+            verbose,
+            format,
+        } => {
+            let params = PlaygroundParams::new(code)
+                .with_files(files)
+                .with_stdin(stdin)
+                .with_args(args)
+                .with_env(env)
+                .with_mode(mode)
+                .with_release(release)
+                .with_edition(edition)
+                .with_limits(limits)
+                .with_verbose(verbose);
+
+            match format {
+                OutputFormat::Text => {
+                    let TextOutput { output, outcome } = run_code_in_playground(&params).await?;
+                    Ok(JobOutput::text(output, outcome))
+                }
+                OutputFormat::Json | OutputFormat::Ndjson => {
+                    let report = run_playground_report(&params).await?;
+                    JobOutput::json(&report, report.outcome())
+                }
+            }
+        }
+
+        Job::RustlingsTest {
+            code,
+            files,
+            verbose,
+            format,
+        } => {
+            let params = rustlings_params(code, files, verbose, format, limits)
+                .with_mode(RustlingsMode::Test);
+            rustlings_output(run_rustlings(&params).await?, format)
+        }
+
+        Job::RustlingsCheck {
+            code,
+            files,
+            verbose,
+            format,
+        } => {
+            let params = rustlings_params(code, files, verbose, format, limits)
+                .with_mode(RustlingsMode::Check);
+            rustlings_output(run_rustlings(&params).await?, format)
+        }
+    }
+}
+
+/// Key of a `test` job in the result cache, with the runner's settings that
+/// change its verdict, serialized as JSON. `None` for the jobs that aren't
+/// cached: the other commands and the jobs opting out
+fn cache_key(job: &Job, config: &JobConfig) -> Option<String> {
+    let Job::Test {
+        code,
+        tests,
+        cargo_toml,
+        hidden_tests,
+        scoring,
+        files,
+        benchmark,
+        reference,
+        verbose,
+        no_cache: false,
+        format,
+    } = job
+    else {
+        return None;
+    };
+    config.cache.as_ref()?;
+
+    let optional = [hidden_tests, scoring, reference];
+    let sources = [code, tests, cargo_toml]
+        .into_iter()
+        .chain(optional.into_iter().flatten())
+        .chain(files.values())
+        .map(String::as_str);
+
+    let limits = &config.limits;
+    // The JSON formats have the same result, the text format its own
+    let options = serde_json::json!({
+        "optional": optional.map(Option::is_some),
+        "files": files.keys().collect::<Vec<_>>(),
+        "benchmark": benchmark,
+        "text": *format == OutputFormat::Text,
+        "verbose": verbose,
+        "limits": {
+            "compile_timeout_ms": limits.compile_timeout.as_millis() as u64,
+            "run_timeout_ms": limits.run_timeout.as_millis() as u64,
+            "max_output_bytes": limits.max_output_bytes,
+            "resources": limits.resources,
+            "sandbox": limits.sandbox,
+        },
+        "manifest_policy": config.manifest_policy,
+        "heap_profiler": config.heap_profiler,
+    });
+
+    Some(job_key(sources, &options))
+}
+
+fn rustlings_params(
+    code: String,
+    files: Files,
+    verbose: bool,
+    format: OutputFormat,
+    limits: Limits,
+) -> RustlingsParams {
+    RustlingsParams::new(code)
+        .with_files(files)
+        .with_diagnostics(format != OutputFormat::Text)
+        .with_limits(limits)
+        .with_verbose(verbose)
+}
+
+/// Rustlings commands fail when the exercise doesn't pass
+fn rustlings_output(result: RustlingsResult, format: OutputFormat) -> anyhow::Result<JobOutput> {
+    match format {
+        OutputFormat::Text => Ok(JobOutput::text(result.output.clone(), result.outcome())),
+        OutputFormat::Json | OutputFormat::Ndjson => JobOutput::json(&result, result.outcome()),
+    }
+}
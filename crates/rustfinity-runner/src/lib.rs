@@ -0,0 +1,100 @@
+//! Runs and grades Rust submissions: the tests of a challenge, the
+//! playground and rustlings exercises, in the runner's sandbox and limits.
+//!
+//! The requests take the code and the files as plain strings, the
+//! `rustfinity-runner` binary decodes its base64 arguments into them. The
+//! scratch crates are modeled after the project at `PROJECT_PATH`, the
+//! challenges' playground by default.
+//!
+//! ```no_run
+//! use rustfinity_runner::{run_tests, RunTestsParams};
+//!
+//! # async fn grade() -> anyhow::Result<()> {
+//! let code = "pub fn add(a: u32, b: u32) -> u32 { a + b }".to_string();
+//! let tests = "#[test]\nfn adds() { assert_eq!(add(1, 2), 3); }".to_string();
+//! let cargo_toml = "[package]\nname = \"add\"\nversion = \"0.1.0\"\n".to_string();
+//!
+//! let verdict = run_tests(&RunTestsParams::new(code, tests, cargo_toml)).await?;
+//! println!("{:?}: {}/100", verdict.outcome(), verdict.score.score);
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! The `cli` feature, on by default, builds the binary: its command line,
+//! the job manifests and the job server. The library doesn't need it.
+
+// The text outputs and the commands that aren't in the API are only used by
+// the binary
+#![cfg_attr(not(feature = "cli"), allow(dead_code))]
+
+#[cfg(feature = "cli")]
+mod app;
+#[cfg(feature = "cli")]
+mod archive;
+mod benchmark;
+#[cfg(feature = "cli")]
+mod cache;
+mod cargo;
+#[cfg(feature = "cli")]
+mod cli;
+mod commands;
+mod constants;
+mod diagnostics;
+mod diff;
+mod edition;
+mod error;
+mod events;
+mod files;
+#[cfg(feature = "cli")]
+mod job;
+mod libtest;
+mod manifest;
+mod memory;
+mod process;
+mod regex;
+mod rlimit;
+mod sandbox;
+mod sanitize;
+mod scoring;
+#[cfg(feature = "cli")]
+mod server;
+mod utils;
+mod verdict;
+mod workspace;
+
+pub use benchmark::BenchmarkOptions;
+pub use commands::{
+    playground::{Mode, PlaygroundParams, PlaygroundReport},
+    run_tests::RunTestsParams,
+    rustlings::{run_rustlings, RustlingsMode, RustlingsParams, RustlingsResult},
+};
+pub use diagnostics::{Diagnostic, Span, Suggestion};
+pub use edition::Edition;
+pub use error::{Outcome, RunnerError};
+pub use events::{Event, Events};
+pub use files::Files;
+pub use manifest::{AllowedCrate, ManifestPolicy};
+pub use memory::HeapProfiler;
+pub use process::{Cancellation, LimitExceeded, Limits};
+pub use rlimit::{Resource, ResourceLimits};
+pub use sandbox::Sandbox;
+pub use scoring::Scoring;
+pub use verdict::{
+    BacktraceFrame, Benchmark, Comparison, CompileStatus, FunctionBenchmark, HeapUsage, Score,
+    TestExit, TestResult, TestStatus, TestTiming, TimeStats, Totals, Verdict,
+};
+
+/// The `rustfinity-runner` binary, not part of the API
+#[cfg(feature = "cli")]
+#[doc(hidden)]
+pub use app::main;
+
+/// Runs the tests of a challenge against a submission and grades it
+pub async fn run_tests(params: &RunTestsParams) -> anyhow::Result<Verdict> {
+    commands::run_tests::run_tests_verdict(params).await
+}
+
+/// Builds the code then runs it, runs its tests or shows it, by its mode
+pub async fn run_playground(params: &PlaygroundParams) -> anyhow::Result<PlaygroundReport> {
+    commands::playground::run_playground_report(params).await
+}
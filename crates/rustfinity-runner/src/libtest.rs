@@ -0,0 +1,434 @@
+use serde::Deserialize;
+
+use crate::verdict::{BacktraceFrame, TestResult, TestStatus};
+
+/// Frames of a backtrace shown to the learner, the closest to the panic
+const MAX_FRAMES: usize = 10;
+
+/// Arguments that make a libtest binary print one JSON event per line.
+///
+/// These are unstable flags, the binary has to be run with `RUSTC_BOOTSTRAP=1`
+pub const JSON_ARGS: [&str; 5] = [
+    "-Z",
+    "unstable-options",
+    "--format",
+    "json",
+    "--report-time",
+];
+
+/// Arguments that make a libtest binary list its tests without running them,
+/// one `<name>: test` line each
+pub const LIST_ARGS: [&str; 3] = ["--list", "--format", "terse"];
+
+#[derive(Debug, Deserialize)]
+struct Event {
+    #[serde(rename = "type")]
+    kind: String,
+    event: String,
+    name: Option<String>,
+    exec_time: Option<f64>,
+    stdout: Option<String>,
+    test_count: Option<usize>,
+    passed: Option<usize>,
+    failed: Option<usize>,
+    ignored: Option<usize>,
+    measured: Option<usize>,
+    filtered_out: Option<usize>,
+}
+
+/// A test starting or finishing, from one line of a test binary's output
+#[derive(Debug, PartialEq)]
+pub enum TestEvent {
+    Started(String),
+    /// With what a failed test printed
+    Finished(TestResult, Option<String>),
+}
+
+/// Parses the output of a test binary run with [`JSON_ARGS`]
+pub fn parse_test_events(output: &str) -> Vec<TestResult> {
+    output
+        .lines()
+        .filter_map(parse_test_event)
+        .filter_map(|event| match event {
+            TestEvent::Finished(result, _) => Some(result),
+            TestEvent::Started(_) => None,
+        })
+        .collect()
+}
+
+/// Parses one line of the output of a test binary run with [`JSON_ARGS`]
+pub fn parse_test_event(line: &str) -> Option<TestEvent> {
+    let event = serde_json::from_str::<Event>(line)
+        .ok()
+        .filter(|event| event.kind == "test")?;
+
+    let status = match event.event.as_str() {
+        "started" => return Some(TestEvent::Started(event.name?)),
+        "ok" => TestStatus::Passed,
+        "failed" | "timeout" => TestStatus::Failed,
+        "ignored" => TestStatus::Ignored,
+        _ => return None,
+    };
+
+    let (message, backtrace) = match (status, event.stdout.as_deref()) {
+        (TestStatus::Failed, Some(stdout)) => (panic_message(stdout), backtrace_frames(stdout)),
+        _ => (None, Vec::new()),
+    };
+
+    Some(TestEvent::Finished(
+        TestResult {
+            name: event.name?,
+            status,
+            message,
+            backtrace,
+            duration_ms: event.exec_time.map(|secs| secs * 1000.0),
+            hidden: false,
+        },
+        event.stdout,
+    ))
+}
+
+/// Tests of the output of a test binary run with [`JSON_ARGS`] that started
+/// but never finished, when the binary died partway through
+pub fn unfinished_tests(output: &str) -> Vec<String> {
+    let mut started = Vec::new();
+
+    for event in output.lines().filter_map(parse_test_event) {
+        match event {
+            TestEvent::Started(name) => started.push(name),
+            TestEvent::Finished(result, _) => started.retain(|name| *name != result.name),
+        }
+    }
+
+    started
+}
+
+/// Whether libtest reported the end of the suite, it doesn't when the
+/// binary exits in the middle of it
+pub fn suite_finished(output: &str) -> bool {
+    output.lines().any(|line| {
+        serde_json::from_str::<Event>(line).is_ok_and(|event| {
+            event.kind == "suite" && matches!(event.event.as_str(), "ok" | "failed")
+        })
+    })
+}
+
+/// The tests a test binary run with [`LIST_ARGS`] has
+pub fn listed_tests(output: &str) -> Vec<String> {
+    output
+        .lines()
+        .filter_map(|line| line.strip_suffix(": test"))
+        .map(str::to_string)
+        .collect()
+}
+
+/// Keeps the results of the `listed` tests, once each. The code can print
+/// events of its own, a test reported more than once failed if one of its
+/// results did
+pub fn reported_results(tests: Vec<TestResult>, listed: &[String]) -> Vec<TestResult> {
+    let mut reported: Vec<TestResult> = Vec::new();
+
+    for test in tests.into_iter().filter(|test| listed.contains(&test.name)) {
+        match reported.iter_mut().find(|result| result.name == test.name) {
+            Some(result) if test.status == TestStatus::Failed => *result = test,
+            Some(_) => {}
+            None => reported.push(test),
+        }
+    }
+
+    reported
+}
+
+/// Prints the output of a test binary run with [`JSON_ARGS`] the way libtest's
+/// pretty format does. The lines that aren't events, e.g. what the code wrote
+/// to stdout itself, are kept as they are
+pub fn pretty_output(output: &str) -> String {
+    let mut pretty = String::new();
+    let mut failures = Vec::new();
+
+    for line in output.lines() {
+        let Ok(event) = serde_json::from_str::<Event>(line) else {
+            pretty.push_str(line);
+            pretty.push('\n');
+            continue;
+        };
+        let name = event.name.unwrap_or_default();
+
+        match (event.kind.as_str(), event.event.as_str()) {
+            ("suite", "started") => {
+                let count = event.test_count.unwrap_or(0);
+                let plural = if count == 1 { "" } else { "s" };
+                pretty.push_str(&format!("\nrunning {} test{}\n", count, plural));
+            }
+            ("test", "ok") => pretty.push_str(&format!("test {} ... ok\n", name)),
+            ("test", "ignored") => pretty.push_str(&format!("test {} ... ignored\n", name)),
+            ("test", "failed" | "timeout") => {
+                pretty.push_str(&format!("test {} ... FAILED\n", name));
+                failures.push((name, event.stdout));
+            }
+            ("suite", status @ ("ok" | "failed")) => {
+                if !failures.is_empty() {
+                    pretty.push_str("\nfailures:\n");
+                    for (name, stdout) in &failures {
+                        if let Some(stdout) = stdout {
+                            pretty.push_str(&format!("\n---- {} stdout ----\n{}", name, stdout));
+                        }
+                    }
+                    pretty.push_str("\nfailures:\n");
+                    for (name, _) in failures.drain(..) {
+                        pretty.push_str(&format!("    {}\n", name));
+                    }
+                }
+
+                pretty.push_str(&format!(
+                    "\ntest result: {}. {} passed; {} failed; {} ignored; {} measured; {} filtered out; finished in {:.2}s\n\n",
+                    if status == "ok" { "ok" } else { "FAILED" },
+                    event.passed.unwrap_or(0),
+                    event.failed.unwrap_or(0),
+                    event.ignored.unwrap_or(0),
+                    event.measured.unwrap_or(0),
+                    event.filtered_out.unwrap_or(0),
+                    event.exec_time.unwrap_or(0.0),
+                ));
+            }
+            _ => {}
+        }
+    }
+
+    pretty
+}
+
+/// Extracts the panic message from the captured output of a failed test,
+/// falls back to the whole output if there is no panic in it
+pub fn panic_message(stdout: &str) -> Option<String> {
+    let mut lines = stdout.lines().skip_while(|line| !is_panic_line(line));
+
+    let message = match lines.next() {
+        Some(_) => lines
+            .take_while(|line| !line.starts_with("note: ") && !line.starts_with("stack backtrace:"))
+            .collect::<Vec<_>>()
+            .join("\n"),
+        None => stdout.to_string(),
+    };
+
+    let message = message.trim();
+
+    if message.is_empty() {
+        None
+    } else {
+        Some(message.to_string())
+    }
+}
+
+pub fn is_panic_line(line: &str) -> bool {
+    line.starts_with("thread '") && line.contains("panicked at")
+}
+
+/// Parses the backtrace printed after a panic with `RUST_BACKTRACE=1`, from
+/// the panic up. Only the frames in the crate are kept, std's and the
+/// dependencies' have absolute paths
+pub fn backtrace_frames(output: &str) -> Vec<BacktraceFrame> {
+    let lines = output
+        .lines()
+        .skip_while(|line| *line != "stack backtrace:")
+        .skip(1)
+        .take_while(|line| line.starts_with(' '));
+
+    let mut frames = Vec::new();
+    let mut function = None;
+
+    for line in lines.map(str::trim) {
+        let Some(location) = line.strip_prefix("at ") else {
+            // `<index>: <function>`, inlined functions have no index
+            let name = match line.split_once(": ") {
+                Some((index, name)) if index.parse::<usize>().is_ok() => name,
+                _ => line,
+            };
+            function = Some(name.to_string());
+            continue;
+        };
+
+        let mut parts = location.rsplitn(3, ':');
+        let (Some(column), Some(line), Some(file), Some(function)) =
+            (parts.next(), parts.next(), parts.next(), function.take())
+        else {
+            continue;
+        };
+        let file = file.strip_prefix("./").unwrap_or(file);
+
+        if let (false, Ok(line), Ok(column)) = (file.starts_with('/'), line.parse(), column.parse())
+        {
+            frames.push(BacktraceFrame {
+                function,
+                file: file.to_string(),
+                line,
+                column,
+            });
+        }
+    }
+
+    frames
+}
+
+/// The first [`MAX_FRAMES`] frames in one of the submitted `files`
+pub fn user_frames(frames: &[BacktraceFrame], files: &[String]) -> Vec<BacktraceFrame> {
+    frames
+        .iter()
+        .filter(|frame| files.contains(&frame.file))
+        .take(MAX_FRAMES)
+        .cloned()
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parses_test_events() {
+        let output = r#"{ "type": "suite", "event": "started", "test_count": 3 }
+{ "type": "test", "event": "started", "name": "a" }
+{ "type": "test", "name": "a", "event": "ok", "exec_time": 0.002 }
+{ "type": "test", "event": "started", "name": "b" }
+{ "type": "test", "name": "b", "event": "failed", "exec_time": 0.026, "stdout": "\nthread 'b' (11919) panicked at tests/tests.rs:1:34:\nassertion `left == right` failed: boom\n  left: 1\n right: 2\nnote: run with `RUST_BACKTRACE=1` environment variable to display a backtrace\n" }
+{ "type": "test", "event": "started", "name": "c" }
+{ "type": "test", "name": "c", "event": "ignored" }
+{ "type": "suite", "event": "failed", "passed": 1, "failed": 1, "ignored": 1, "measured": 0, "filtered_out": 0, "exec_time": 0.031 }
+"#;
+
+        let tests = parse_test_events(output);
+        assert!(suite_finished(output));
+        assert!(pretty_output(output).ends_with(
+            "test a ... ok
+test b ... FAILED
+test c ... ignored
+
+failures:
+
+---- b stdout ----
+
+thread 'b' (11919) panicked at tests/tests.rs:1:34:
+assertion `left == right` failed: boom
+  left: 1
+ right: 2
+note: run with `RUST_BACKTRACE=1` environment variable to display a backtrace
+
+failures:
+    b
+
+test result: FAILED. 1 passed; 1 failed; 1 ignored; 0 measured; 0 filtered out; finished in 0.03s
+
+"
+        ));
+        assert!(unfinished_tests(output).is_empty());
+
+        // The binary crashed during `b`
+        let crashed = output.lines().take(4).collect::<Vec<_>>().join("\n");
+        assert!(!suite_finished(&crashed));
+        assert_eq!(unfinished_tests(&crashed), ["b"]);
+
+        assert_eq!(
+            tests,
+            vec![
+                TestResult {
+                    name: "a".to_string(),
+                    status: TestStatus::Passed,
+                    message: None,
+                    backtrace: Vec::new(),
+                    duration_ms: Some(2.0),
+                    hidden: false,
+                },
+                TestResult {
+                    name: "b".to_string(),
+                    status: TestStatus::Failed,
+                    message: Some(
+                        "assertion `left == right` failed: boom\n  left: 1\n right: 2".to_string()
+                    ),
+                    backtrace: Vec::new(),
+                    duration_ms: Some(26.0),
+                    hidden: false,
+                },
+                TestResult {
+                    name: "c".to_string(),
+                    status: TestStatus::Ignored,
+                    message: None,
+                    backtrace: Vec::new(),
+                    duration_ms: None,
+                    hidden: false,
+                },
+            ]
+        );
+
+        // Printed by the code: a passing `b` and a test that doesn't exist
+        let printed = format!(
+            "{}{{ \"type\": \"test\", \"name\": \"b\", \"event\": \"ok\" }}\n{{ \"type\": \"test\", \"name\": \"fake\", \"event\": \"ok\" }}\n",
+            output
+        );
+        let listed = listed_tests("a: test\nb: test\nc: test\n\n3 tests, 0 benchmarks\n");
+        assert_eq!(
+            reported_results(parse_test_events(&printed), &listed),
+            tests
+        );
+    }
+
+    #[test]
+    fn test_parses_user_frames() {
+        let stdout = "
+thread 'tests::zero' (2214) panicked at src/lib.rs:3:9:
+attempt to subtract with overflow
+stack backtrace:
+   0: __rustc::rust_begin_unwind
+             at /rustc/1159e78c4747b02ef996e55082b704c09b970588/library/std/src/panicking.rs:697:5
+   1: core::panicking::panic_const::panic_const_sub_overflow
+             at /rustc/1159e78c4747b02ef996e55082b704c09b970588/library/core/src/panicking.rs:175:17
+   2: fibonacci::fibonacci
+             at ./src/lib.rs:3:9
+   3: fibonacci::helpers::previous
+             at ./src/helpers.rs:2:5
+      fibonacci::fibonacci
+             at ./src/lib.rs:5:13
+   4: tests::tests::zero
+             at ./tests/tests.rs:7:20
+   5: <unknown>
+note: Some details are omitted, run with `RUST_BACKTRACE=full` for a verbose backtrace.
+";
+
+        let frames = backtrace_frames(stdout);
+        assert_eq!(frames.len(), 4);
+        assert_eq!(frames[3].file, "tests/tests.rs");
+
+        let files = ["src/lib.rs".to_string(), "src/helpers.rs".to_string()];
+        assert_eq!(
+            user_frames(&frames, &files),
+            vec![
+                BacktraceFrame {
+                    function: "fibonacci::fibonacci".to_string(),
+                    file: "src/lib.rs".to_string(),
+                    line: 3,
+                    column: 9,
+                },
+                BacktraceFrame {
+                    function: "fibonacci::helpers::previous".to_string(),
+                    file: "src/helpers.rs".to_string(),
+                    line: 2,
+                    column: 5,
+                },
+                BacktraceFrame {
+                    function: "fibonacci::fibonacci".to_string(),
+                    file: "src/lib.rs".to_string(),
+                    line: 5,
+                    column: 13,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_panic_message_without_panic() {
+        assert_eq!(
+            panic_message("Error: \"invalid input\"\n"),
+            Some("Error: \"invalid input\"".to_string())
+        );
+        assert_eq!(panic_message("\n"), None);
+    }
+}
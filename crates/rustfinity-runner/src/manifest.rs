@@ -0,0 +1,509 @@
+use std::{
+    collections::BTreeMap,
+    fmt, fs,
+    path::{Component, Path},
+};
+
+use anyhow::Context;
+use semver::{Op, Version, VersionReq};
+use serde::{Deserialize, Serialize};
+use toml::{Table, Value};
+
+/// What a submitted Cargo.toml may contain. The default allows the crates the
+/// container has, a custom policy is loaded with `--manifest-policy`
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ManifestPolicy {
+    /// Editions the package can use
+    pub editions: Vec<String>,
+    /// Whether the library can be a procedural macro
+    pub allow_proc_macro: bool,
+    /// Crates the package can depend on, by name
+    pub crates: BTreeMap<String, AllowedCrate>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(untagged)]
+pub enum AllowedCrate {
+    /// A crates.io crate with the versions the container has, e.g. `"2"`
+    Registry(VersionReq),
+    /// A local crate, depended on with exactly this path
+    Path { path: String },
+}
+
+impl Default for ManifestPolicy {
+    fn default() -> Self {
+        let registry = |version: &str| {
+            AllowedCrate::Registry(VersionReq::parse(version).expect("valid version requirement"))
+        };
+
+        Self {
+            editions: ["2015", "2018", "2021", "2024"].map(String::from).to_vec(),
+            allow_proc_macro: true,
+            crates: BTreeMap::from([
+                ("syn".to_string(), registry("2")),
+                ("quote".to_string(), registry("1")),
+                ("tempfile".to_string(), registry("3")),
+                (
+                    "syntest".to_string(),
+                    AllowedCrate::Path {
+                        path: "../../crates/syntest".to_string(),
+                    },
+                ),
+            ]),
+        }
+    }
+}
+
+/// The reasons a manifest isn't allowed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PolicyViolation {
+    pub violations: Vec<String>,
+}
+
+impl fmt::Display for PolicyViolation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Cargo.toml isn't allowed by the runner:")?;
+        for violation in &self.violations {
+            write!(f, "\n- {}", violation)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for PolicyViolation {}
+
+/// Sections of the manifest that are checked or can't change how the crate is built
+const ALLOWED_SECTIONS: [&str; 12] = [
+    "package",
+    "lib",
+    "bin",
+    "test",
+    "example",
+    "bench",
+    "dependencies",
+    "dev-dependencies",
+    "target",
+    "features",
+    "lints",
+    "badges",
+];
+
+/// Test targets the runner adds, the challenge's tests and the hidden tests
+const RESERVED_TARGETS: [&str; 2] = ["tests", "hidden"];
+
+impl ManifestPolicy {
+    pub fn load(path: &str) -> anyhow::Result<Self> {
+        let policy = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read the manifest policy {}", path))?;
+        toml::from_str(&policy).with_context(|| format!("Invalid manifest policy {}", path))
+    }
+
+    /// Checks the manifest and returns it normalised, with build scripts
+    /// turned off so a submitted `build.rs` isn't picked up
+    pub(crate) fn apply(&self, cargo_toml: &str) -> Result<String, PolicyViolation> {
+        let mut manifest = cargo_toml.parse::<Table>().map_err(|e| PolicyViolation {
+            violations: vec![format!("it isn't valid TOML: {}", e.message())],
+        })?;
+
+        let mut violations = Vec::new();
+
+        for section in manifest.keys() {
+            match section.as_str() {
+                section if ALLOWED_SECTIONS.contains(&section) => {}
+                "profile" => violations.push("profiles can't be changed".to_string()),
+                "build-dependencies" => violations.push(
+                    "`[build-dependencies]` aren't allowed, build scripts don't run".to_string(),
+                ),
+                section => violations.push(format!("`[{}]` isn't allowed", section)),
+            }
+        }
+
+        if let Some(targets) = manifest.get("target").and_then(Value::as_table) {
+            for (target, sections) in targets {
+                for section in sections.as_table().into_iter().flat_map(Table::keys) {
+                    if section != "dependencies" && section != "dev-dependencies" {
+                        violations.push(format!("`[target.{}.{}]` isn't allowed", target, section));
+                    }
+                }
+            }
+        }
+
+        match manifest.get_mut("package").and_then(Value::as_table_mut) {
+            Some(package) => {
+                self.check_package(package, &mut violations);
+                package.insert("build".to_string(), Value::Boolean(false));
+            }
+            None => violations.push("`[package]` is missing".to_string()),
+        }
+
+        if let Some(lib) = manifest.get("lib").and_then(Value::as_table) {
+            let proc_macro = ["proc-macro", "proc_macro"]
+                .iter()
+                .any(|key| lib.get(*key).and_then(Value::as_bool) == Some(true));
+
+            if proc_macro && !self.allow_proc_macro {
+                violations.push("procedural macro crates aren't allowed".to_string());
+            }
+        }
+
+        for (section, target) in target_tables(&manifest) {
+            match target.as_table() {
+                Some(target) => check_target(&section, target, &mut violations),
+                None => violations.push(format!("`{}` must be a table", section)),
+            }
+        }
+
+        for (section, dependencies) in dependency_tables(&manifest) {
+            match dependencies.as_table() {
+                Some(dependencies) => {
+                    for (name, dependency) in dependencies {
+                        self.check_dependency(name, dependency, &mut violations);
+                    }
+                }
+                None => violations.push(format!("`{}` must be a table", section)),
+            }
+        }
+
+        if !violations.is_empty() {
+            return Err(PolicyViolation { violations });
+        }
+
+        Ok(manifest.to_string())
+    }
+
+    fn check_package(&self, package: &Table, violations: &mut Vec<String>) {
+        // Cargo defaults to the 2015 edition
+        match package.get("edition").map(Value::as_str) {
+            None if self.editions.iter().any(|edition| edition == "2015") => {}
+            Some(Some(edition)) if self.editions.iter().any(|allowed| allowed == edition) => {}
+            _ => violations.push(format!(
+                "the edition must be one of {}",
+                self.editions.join(", ")
+            )),
+        }
+
+        if package
+            .get("build")
+            .is_some_and(|build| build.as_bool() != Some(false))
+        {
+            violations.push("build scripts aren't allowed".to_string());
+        }
+
+        if package.contains_key("links") {
+            violations.push("`links` isn't allowed, native libraries can't be linked".to_string());
+        }
+
+        if package.contains_key("workspace") {
+            violations.push("`package.workspace` isn't allowed".to_string());
+        }
+    }
+
+    fn check_dependency(&self, name: &str, dependency: &Value, violations: &mut Vec<String>) {
+        let empty = Table::new();
+        let (version, details) = match dependency {
+            Value::String(version) => (Some(version.as_str()), &empty),
+            Value::Table(details) => (details.get("version").and_then(Value::as_str), details),
+            _ => {
+                violations.push(format!("`{}` isn't a valid dependency", name));
+                return;
+            }
+        };
+
+        // Renamed dependencies, `name = { package = "syn" }`
+        let crate_name = details
+            .get("package")
+            .and_then(Value::as_str)
+            .unwrap_or(name);
+
+        let Some(allowed) = self.crates.get(crate_name) else {
+            violations.push(format!(
+                "`{}` isn't available, the crates that can be used are {}",
+                crate_name,
+                self.crates.keys().cloned().collect::<Vec<_>>().join(", ")
+            ));
+            return;
+        };
+
+        for (key, what) in [
+            ("git", "git dependencies"),
+            ("registry", "other registries"),
+            ("registry-index", "other registries"),
+            ("workspace", "workspace dependencies"),
+        ] {
+            if details.contains_key(key) {
+                violations.push(format!("`{}`: {} aren't allowed", crate_name, what));
+            }
+        }
+
+        match allowed {
+            AllowedCrate::Registry(allowed) => {
+                if details.contains_key("path") {
+                    violations.push(format!(
+                        "`{}`: path dependencies aren't allowed",
+                        crate_name
+                    ));
+                }
+
+                if let Some(version) = version {
+                    if !version_allowed(version, allowed) {
+                        violations.push(format!(
+                            "`{} = \"{}\"` isn't available, the versions that can be used are `{}`",
+                            crate_name, version, allowed
+                        ));
+                    }
+                }
+            }
+            AllowedCrate::Path { path } => {
+                let same_path = details
+                    .get("path")
+                    .and_then(Value::as_str)
+                    .is_some_and(|dependency_path| Path::new(dependency_path) == Path::new(path));
+
+                if !same_path {
+                    violations.push(format!(
+                        "`{}` must be a path dependency on `{}`",
+                        crate_name, path
+                    ));
+                }
+            }
+        }
+    }
+}
+
+/// The name the library is used with, `[lib] name` or the package name with
+/// `-` replaced by `_`
+pub fn lib_name(cargo_toml: &str) -> Option<String> {
+    let manifest = cargo_toml.parse::<Table>().ok()?;
+
+    if let Some(name) = manifest
+        .get("lib")
+        .and_then(|lib| lib.get("name"))
+        .and_then(Value::as_str)
+    {
+        return Some(name.to_string());
+    }
+
+    package_name(cargo_toml).map(|name| name.replace('-', "_"))
+}
+
+/// `package.name`, also the name of the `src/main.rs` binary
+pub fn package_name(cargo_toml: &str) -> Option<String> {
+    let manifest = cargo_toml.parse::<Table>().ok()?;
+
+    manifest
+        .get("package")?
+        .get("name")?
+        .as_str()
+        .map(str::to_string)
+}
+
+/// `[lib]` and each `[[bin]]`, `[[test]]`, `[[example]]` and `[[bench]]`
+fn target_tables(manifest: &Table) -> Vec<(String, &Value)> {
+    let mut tables = Vec::new();
+
+    if let Some(lib) = manifest.get("lib") {
+        tables.push(("[lib]".to_string(), lib));
+    }
+
+    for section in ["bin", "test", "example", "bench"] {
+        let section_name = format!("[[{}]]", section);
+
+        match manifest.get(section) {
+            Some(Value::Array(targets)) => {
+                tables.extend(targets.iter().map(|target| (section_name.clone(), target)))
+            }
+            Some(target) => tables.push((section_name, target)),
+            None => {}
+        }
+    }
+
+    tables
+}
+
+/// A target can't be built from outside the crate, replace the runner's test
+/// targets or run without libtest
+fn check_target(section: &str, target: &Table, violations: &mut Vec<String>) {
+    if let Some(name) = target.get("name").and_then(Value::as_str) {
+        if RESERVED_TARGETS.contains(&name) {
+            violations.push(format!(
+                "`{}`: the name `{}` is reserved for the challenge's tests",
+                section, name
+            ));
+        }
+    }
+
+    if let Some(path) = target.get("path") {
+        let inside_crate = path.as_str().map(Path::new).is_some_and(|path| {
+            path.is_relative()
+                && !path
+                    .components()
+                    .any(|component| component == Component::ParentDir)
+        });
+
+        if !inside_crate {
+            violations.push(format!(
+                "`{}`: `path` must be a relative path inside the crate",
+                section
+            ));
+        }
+    }
+
+    if target.get("harness").and_then(Value::as_bool) == Some(false) {
+        violations.push(format!(
+            "`{}`: `harness = false` isn't allowed, the tests run with libtest",
+            section
+        ));
+    }
+}
+
+/// `[dependencies]`, `[dev-dependencies]` and the ones of `[target.'cfg(..)']`
+fn dependency_tables(manifest: &Table) -> Vec<(String, &Value)> {
+    let mut tables = Vec::new();
+
+    for section in ["dependencies", "dev-dependencies"] {
+        if let Some(dependencies) = manifest.get(section) {
+            tables.push((format!("[{}]", section), dependencies));
+        }
+    }
+
+    if let Some(targets) = manifest.get("target").and_then(Value::as_table) {
+        for (target, sections) in targets {
+            let Some(sections) = sections.as_table() else {
+                continue;
+            };
+
+            for section in ["dependencies", "dev-dependencies"] {
+                if let Some(dependencies) = sections.get(section) {
+                    tables.push((format!("[target.{}.{}]", target, section), dependencies));
+                }
+            }
+        }
+    }
+
+    tables
+}
+
+/// Whether the lowest version the requirement accepts is allowed, `2.0.96`
+/// is allowed by `2` but `1` and `*` aren't
+fn version_allowed(version: &str, allowed: &VersionReq) -> bool {
+    let Ok(requirement) = VersionReq::parse(version) else {
+        return false;
+    };
+
+    let lowest = requirement
+        .comparators
+        .iter()
+        .filter(|comparator| {
+            matches!(
+                comparator.op,
+                Op::Exact | Op::Greater | Op::GreaterEq | Op::Tilde | Op::Caret | Op::Wildcard
+            )
+        })
+        .map(|comparator| Version {
+            major: comparator.major,
+            minor: comparator.minor.unwrap_or(0),
+            patch: comparator.patch.unwrap_or(0),
+            pre: comparator.pre.clone(),
+            build: Default::default(),
+        })
+        .max()
+        .unwrap_or(Version::new(0, 0, 0));
+
+    allowed.matches(&lowest)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn violations(cargo_toml: &str) -> Vec<String> {
+        ManifestPolicy::default()
+            .apply(cargo_toml)
+            .unwrap_err()
+            .violations
+    }
+
+    #[test]
+    fn test_allows_challenge_manifests() {
+        let manifest = ManifestPolicy::default()
+            .apply(
+                r#"[package]
+name = "procedural-macros"
+version = "0.1.0"
+edition = "2021"
+
+[lib]
+proc-macro = true
+
+[dependencies]
+quote = "1.0.38"
+syn = { version = "2.0.96", features = ["full"] }
+
+[dev-dependencies]
+syntest = { path = "../../crates/syntest" }
+"#,
+            )
+            .unwrap();
+
+        let manifest = manifest.parse::<Table>().unwrap();
+        assert_eq!(manifest["package"]["build"].as_bool(), Some(false));
+        assert_eq!(
+            manifest["dependencies"]["syn"]["features"].as_array(),
+            Some(&vec![Value::from("full")])
+        );
+    }
+
+    #[test]
+    fn test_rejects_manifests_outside_the_policy() {
+        let violations = violations(
+            r#"[package]
+name = "evil"
+version = "0.1.0"
+edition = "2021"
+build = "build.rs"
+links = "z"
+
+[dependencies]
+rand = "0.8"
+syn = "1"
+quote = { git = "https://github.com/dtolnay/quote" }
+syntest = { path = "/app/crates/other" }
+
+[[bin]]
+name = "tests"
+path = "../../etc/main.rs"
+
+[[test]]
+name = "hidden"
+path = "/tmp/hidden.rs"
+harness = false
+
+[build-dependencies]
+cc = "1"
+
+[profile.dev]
+opt-level = 3
+"#,
+        );
+
+        assert_eq!(
+            violations,
+            [
+                "`[build-dependencies]` aren't allowed, build scripts don't run",
+                "profiles can't be changed",
+                "build scripts aren't allowed",
+                "`links` isn't allowed, native libraries can't be linked",
+                "`[[bin]]`: the name `tests` is reserved for the challenge's tests",
+                "`[[bin]]`: `path` must be a relative path inside the crate",
+                "`[[test]]`: the name `hidden` is reserved for the challenge's tests",
+                "`[[test]]`: `path` must be a relative path inside the crate",
+                "`[[test]]`: `harness = false` isn't allowed, the tests run with libtest",
+                "`rand` isn't available, the crates that can be used are quote, syn, syntest, tempfile",
+                "`syn = \"1\"` isn't available, the versions that can be used are `^2`",
+                "`quote`: git dependencies aren't allowed",
+                "`syntest` must be a path dependency on `../../crates/syntest`",
+            ]
+        );
+    }
+}
@@ -0,0 +1,271 @@
+use std::{fs, path::Path, process::Command};
+
+use anyhow::Context;
+use serde::Serialize;
+
+use crate::{
+    process::{run, Limits, Phase},
+    verdict::HeapUsage,
+};
+
+/// How the heap usage of the tests is measured, the peak RSS is always
+/// measured
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+#[serde(rename_all = "lowercase")]
+pub enum HeapProfiler {
+    /// Only the peak RSS
+    None,
+    /// A counting allocator added to the tests crate, measured during the
+    /// timed runs. Left out when the submission has its own global allocator
+    #[default]
+    Allocator,
+    /// An extra run of the tests under heaptrack
+    Heaptrack,
+}
+
+/// Environment variable with the path the counting allocator writes its
+/// stats to when the tests exit
+pub const ALLOCATOR_STATS_ENV: &str = "RUSTFINITY_ALLOC_STATS";
+
+/// Appended to the tests, the module writes `<peak bytes> <allocations>
+/// <allocated bytes>` to the stats file when the process exits
+const COUNTING_ALLOCATOR: &str = r#"
+
+#[doc(hidden)]
+#[allow(warnings)]
+mod __rustfinity_counting_allocator {
+    use std::alloc::{GlobalAlloc, Layout, System};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    static CURRENT: AtomicUsize = AtomicUsize::new(0);
+    static PEAK: AtomicUsize = AtomicUsize::new(0);
+    static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+    static ALLOCATED: AtomicUsize = AtomicUsize::new(0);
+
+    fn grow(size: usize) {
+        ALLOCATED.fetch_add(size, Ordering::Relaxed);
+        let current = CURRENT.fetch_add(size, Ordering::Relaxed) + size;
+        PEAK.fetch_max(current, Ordering::Relaxed);
+    }
+
+    struct Counting;
+
+    unsafe impl GlobalAlloc for Counting {
+        unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+            let ptr = unsafe { System.alloc(layout) };
+            if !ptr.is_null() {
+                ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+                grow(layout.size());
+            }
+            ptr
+        }
+
+        unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+            let ptr = unsafe { System.alloc_zeroed(layout) };
+            if !ptr.is_null() {
+                ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+                grow(layout.size());
+            }
+            ptr
+        }
+
+        unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+            unsafe { System.dealloc(ptr, layout) };
+            CURRENT.fetch_sub(layout.size(), Ordering::Relaxed);
+        }
+
+        unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+            let new_ptr = unsafe { System.realloc(ptr, layout, new_size) };
+            if !new_ptr.is_null() {
+                CURRENT.fetch_sub(layout.size(), Ordering::Relaxed);
+                grow(new_size);
+            }
+            new_ptr
+        }
+    }
+
+    #[global_allocator]
+    static ALLOCATOR: Counting = Counting;
+
+    extern "C" fn write_stats() {
+        if let Some(path) = std::env::var_os("RUSTFINITY_ALLOC_STATS") {
+            let _ = std::fs::write(
+                path,
+                format!(
+                    "{} {} {}",
+                    PEAK.load(Ordering::Relaxed),
+                    ALLOCATIONS.load(Ordering::Relaxed),
+                    ALLOCATED.load(Ordering::Relaxed)
+                ),
+            );
+        }
+    }
+
+    #[used]
+    #[unsafe(link_section = ".fini_array")]
+    static WRITE_STATS: extern "C" fn() = write_stats;
+}
+"#;
+
+impl HeapProfiler {
+    /// The tests with the counting allocator when it's used. A crate can only
+    /// have one global allocator, so the submission's sources are checked
+    pub fn instrument_tests(&self, tests: &str, sources: &[&str]) -> String {
+        let has_allocator = sources
+            .iter()
+            .chain([&tests])
+            .any(|source| source.contains("global_allocator"));
+
+        if *self != HeapProfiler::Allocator || has_allocator {
+            return tests.to_string();
+        }
+
+        format!("{}{}", tests, COUNTING_ALLOCATOR)
+    }
+}
+
+/// Reads the stats the counting allocator wrote, `None` when the tests weren't
+/// instrumented or didn't exit normally
+pub fn read_allocator_stats(path: &Path) -> Option<HeapUsage> {
+    let stats = fs::read_to_string(path).ok()?;
+    let mut numbers = stats.split_whitespace().map(str::parse::<u64>);
+
+    Some(HeapUsage {
+        peak_bytes: numbers.next()?.ok()?,
+        allocations: numbers.next()?.ok()?,
+        allocated_bytes: Some(numbers.next()?.ok()?),
+    })
+}
+
+/// Runs the test binary under heaptrack and reads the peak heap memory and
+/// the number of allocations from its analysis
+pub fn heaptrack(
+    workspace: &Path,
+    test_binary_path: &str,
+    limits: &Limits,
+) -> anyhow::Result<HeapUsage> {
+    let output = run(
+        Command::new("heaptrack")
+            .arg(test_binary_path)
+            .current_dir(workspace),
+        Phase::Run,
+        limits,
+    )
+    .context("Failed to run heaptrack, is it installed?")?
+    .check_limits()?;
+
+    let output_path = output
+        .stdout
+        .lines()
+        .find(|line| line.contains("heaptrack output will be written to"))
+        .and_then(|line| line.split('"').nth(1))
+        .context("heaptrack didn't print the path of its output")?;
+
+    let analysis = run(
+        Command::new("heaptrack")
+            .arg("--analyze")
+            .arg(output_path)
+            .current_dir(workspace),
+        Phase::Run,
+        limits,
+    )?
+    .check_limits()?;
+
+    parse_heaptrack_analysis(&analysis.stdout)
+        .context("Failed to read the peak heap memory from the heaptrack analysis")
+}
+
+/// Reads `peak heap memory consumption: 1.02K` and `calls to allocation
+/// functions: 12 (1200/s)` from the summary at the end of the analysis
+fn parse_heaptrack_analysis(analysis: &str) -> Option<HeapUsage> {
+    let value = |label: &str| {
+        analysis
+            .lines()
+            .rev()
+            .find_map(|line| line.split_once(label))
+            .map(|(_, value)| value.trim())
+    };
+
+    let allocations = value("calls to allocation functions:")?
+        .split_whitespace()
+        .next()?
+        .parse()
+        .ok()?;
+
+    Some(HeapUsage {
+        peak_bytes: parse_size(value("peak heap memory consumption:")?)?,
+        allocations,
+        allocated_bytes: None,
+    })
+}
+
+/// Parses heaptrack's sizes, e.g. `512B`, `1.02K` or `3.40M`
+fn parse_size(size: &str) -> Option<u64> {
+    let unit_start = size.find(|c: char| c.is_ascii_alphabetic())?;
+    let (number, unit) = size.split_at(unit_start);
+    let multiplier = match unit {
+        "B" => 1.0,
+        "K" => 1024.0,
+        "M" => 1024.0 * 1024.0,
+        "G" => 1024.0 * 1024.0 * 1024.0,
+        _ => return None,
+    };
+
+    Some((number.parse::<f64>().ok()? * multiplier).round() as u64)
+}
+
+/// Formats bytes like heaptrack does, e.g. `1.02K`
+pub fn format_size(bytes: u64) -> String {
+    let units = ["K", "M", "G"];
+    let mut size = bytes as f64;
+    let mut unit = "B";
+
+    for next in units {
+        if size < 1000.0 {
+            break;
+        }
+        size /= 1024.0;
+        unit = next;
+    }
+
+    if unit == "B" {
+        format!("{}B", bytes)
+    } else {
+        format!("{:.2}{}", size, unit)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parses_heaptrack_analysis() {
+        let analysis =
+            "reading file \"heaptrack.tests.1.zst\" - please wait, this might take some time...\n\
+            Debuggee command was: tests\n\
+            finished reading file, now analyzing data:\n\n\
+            total runtime: 0.01s.\n\
+            calls to allocation functions: 31 (3100/s)\n\
+            temporary memory allocations: 3 (300/s)\n\
+            peak heap memory consumption: 1.02K\n\
+            peak RSS (including heaptrack overhead): 5.50M\n\
+            total memory leaked: 0B\n";
+
+        assert_eq!(
+            parse_heaptrack_analysis(analysis),
+            Some(HeapUsage {
+                peak_bytes: 1044,
+                allocations: 31,
+                allocated_bytes: None,
+            })
+        );
+        assert_eq!(
+            parse_heaptrack_analysis("heaptrack: command not found"),
+            None
+        );
+        assert_eq!(format_size(1044), "1.02K");
+        assert_eq!(format_size(512), "512B");
+    }
+}
@@ -0,0 +1,726 @@
+use std::{
+    fmt,
+    io::{self, Read, Write},
+    mem,
+    os::{
+        fd::{AsFd, AsRawFd, BorrowedFd},
+        unix::process::{CommandExt, ExitStatusExt},
+    },
+    process::{Command, ExitStatus, Stdio},
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        mpsc, Arc, Mutex,
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+use serde::Serialize;
+
+use crate::{
+    rlimit::{Resource, ResourceLimits},
+    sandbox::Sandbox,
+};
+
+/// Limits applied to every command the runner spawns
+#[derive(Debug, Clone)]
+pub struct Limits {
+    pub compile_timeout: Duration,
+    pub run_timeout: Duration,
+    /// Maximum bytes captured from stdout and stderr together
+    pub max_output_bytes: usize,
+    /// Only applied to the commands that run the submitted code
+    pub resources: ResourceLimits,
+    /// Only wraps the commands that run the submitted code
+    pub sandbox: Sandbox,
+    pub cancellation: Cancellation,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Self {
+            compile_timeout: Duration::from_secs(60),
+            run_timeout: Duration::from_secs(10),
+            max_output_bytes: 1024 * 1024,
+            resources: ResourceLimits::default(),
+            sandbox: Sandbox::default(),
+            cancellation: Cancellation::default(),
+        }
+    }
+}
+
+impl Limits {
+    pub(crate) fn timeout(&self, phase: Phase) -> Duration {
+        match phase {
+            Phase::Compile => self.compile_timeout,
+            Phase::Run => self.run_timeout,
+        }
+    }
+}
+
+/// Cancels a job, shared by all the commands of the job. Cancelling kills the
+/// command that's running and makes the next ones fail to spawn
+#[derive(Debug, Clone, Default)]
+pub struct Cancellation(Arc<Mutex<CancellationState>>);
+
+#[derive(Debug, Default)]
+struct CancellationState {
+    cancelled: bool,
+    /// Process groups of the commands that are running
+    running: Vec<i32>,
+}
+
+impl Cancellation {
+    pub fn cancel(&self) {
+        let mut state = self.0.lock().unwrap();
+        state.cancelled = true;
+
+        for pgid in &state.running {
+            kill_group(*pgid);
+        }
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.lock().unwrap().cancelled
+    }
+
+    fn register(&self, pgid: i32) -> io::Result<()> {
+        let mut state = self.0.lock().unwrap();
+
+        if state.cancelled {
+            return Err(cancelled());
+        }
+        state.running.push(pgid);
+
+        Ok(())
+    }
+
+    fn unregister(&self, pgid: i32) {
+        self.0
+            .lock()
+            .unwrap()
+            .running
+            .retain(|running| *running != pgid);
+    }
+}
+
+fn cancelled() -> io::Error {
+    io::Error::new(io::ErrorKind::Interrupted, "The job was cancelled")
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Phase {
+    Compile,
+    Run,
+}
+
+/// A limit that was hit, the process group was killed because of it or the
+/// kernel stopped the process when it went over a resource limit
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum LimitExceeded {
+    TimedOut {
+        phase: Phase,
+        limit_ms: u64,
+    },
+    OutputTruncated {
+        phase: Phase,
+        limit_bytes: usize,
+    },
+    ResourceLimit {
+        phase: Phase,
+        resource: Resource,
+        limit: u64,
+    },
+}
+
+impl fmt::Display for LimitExceeded {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LimitExceeded::TimedOut {
+                phase: Phase::Compile,
+                limit_ms,
+            } => write!(
+                f,
+                "Compilation timed out after {}s",
+                *limit_ms as f64 / 1000.0
+            ),
+            LimitExceeded::TimedOut {
+                phase: Phase::Run,
+                limit_ms,
+            } => write!(
+                f,
+                "Execution timed out after {}s",
+                *limit_ms as f64 / 1000.0
+            ),
+            LimitExceeded::OutputTruncated { limit_bytes, .. } => {
+                write!(f, "Output truncated after {} bytes", limit_bytes)
+            }
+            LimitExceeded::ResourceLimit {
+                resource, limit, ..
+            } => write!(f, "{} exceeded", resource.describe(*limit)),
+        }
+    }
+}
+
+impl std::error::Error for LimitExceeded {}
+
+#[derive(Debug)]
+pub struct ProcessOutput {
+    pub status: ExitStatus,
+    pub stdout: String,
+    /// Empty when the output was merged into stdout
+    pub stderr: String,
+    pub limit_exceeded: Option<LimitExceeded>,
+    /// Peak resident set size of the process, or of a child it waited for
+    pub peak_rss_bytes: u64,
+}
+
+impl ProcessOutput {
+    pub fn success(&self) -> bool {
+        self.limit_exceeded.is_none() && self.status.success()
+    }
+
+    /// Turns a hit limit into an error, for callers that can't use a partial output
+    pub fn check_limits(self) -> Result<Self, LimitExceeded> {
+        match self.limit_exceeded {
+            Some(exceeded) => Err(exceeded),
+            None => Ok(self),
+        }
+    }
+}
+
+/// Runs the command in its own process group with the limits of `phase`,
+/// stdout and stderr are captured separately. Commands of [`Phase::Run`] also
+/// get the resource limits and the sandbox
+pub fn run(command: &mut Command, phase: Phase, limits: &Limits) -> io::Result<ProcessOutput> {
+    command.stdout(Stdio::piped()).stderr(Stdio::piped());
+    spawn_and_wait(command, phase, limits, None, None, None)
+}
+
+/// Same as [`run`] with `input` written to the command's stdin
+pub fn run_with_input(
+    command: &mut Command,
+    input: &[u8],
+    phase: Phase,
+    limits: &Limits,
+) -> io::Result<ProcessOutput> {
+    command.stdout(Stdio::piped()).stderr(Stdio::piped());
+    spawn_and_wait(command, phase, limits, None, Some(input.to_vec()), None)
+}
+
+/// Same as [`run`] with `on_line` called with each line of stdout and stderr
+/// as it's written, from the threads reading them
+pub fn run_streaming(
+    command: &mut Command,
+    phase: Phase,
+    limits: &Limits,
+    on_line: impl Fn(&str) + Send + Sync + 'static,
+) -> io::Result<ProcessOutput> {
+    command.stdout(Stdio::piped()).stderr(Stdio::piped());
+    spawn_and_wait(command, phase, limits, None, None, Some(Arc::new(on_line)))
+}
+
+/// Same as [`run`] but stderr is merged into stdout, in the order it was written
+pub fn run_merged(
+    command: &mut Command,
+    phase: Phase,
+    limits: &Limits,
+) -> io::Result<ProcessOutput> {
+    let (reader, writer) = io::pipe()?;
+    command.stdout(writer.try_clone()?).stderr(writer);
+    spawn_and_wait(command, phase, limits, Some(reader), None, None)
+}
+
+/// Same as [`run_merged`] with `input` written to the command's stdin
+pub fn run_merged_with_input(
+    command: &mut Command,
+    input: &[u8],
+    phase: Phase,
+    limits: &Limits,
+) -> io::Result<ProcessOutput> {
+    let (reader, writer) = io::pipe()?;
+    command.stdout(writer.try_clone()?).stderr(writer);
+    spawn_and_wait(
+        command,
+        phase,
+        limits,
+        Some(reader),
+        Some(input.to_vec()),
+        None,
+    )
+}
+
+fn spawn_and_wait(
+    command: &mut Command,
+    phase: Phase,
+    limits: &Limits,
+    merged: Option<io::PipeReader>,
+    input: Option<Vec<u8>>,
+    on_line: Option<OnLine>,
+) -> io::Result<ProcessOutput> {
+    if limits.cancellation.is_cancelled() {
+        return Err(cancelled());
+    }
+
+    if phase == Phase::Run {
+        limits.resources.apply(command);
+        limits.sandbox.apply(command)?;
+    }
+
+    let stdin = match input {
+        Some(_) => Stdio::piped(),
+        None => Stdio::null(),
+    };
+    let spawned = command.stdin(stdin).process_group(0).spawn();
+    // The command holds our copy of the merged pipe's write end, reading
+    // would never finish if it stayed open
+    command.stdout(Stdio::null()).stderr(Stdio::null());
+    let mut child = spawned?;
+
+    let pgid = child.id() as i32;
+    if let Err(e) = limits.cancellation.register(pgid) {
+        kill_group(pgid);
+        child.wait()?;
+        return Err(e);
+    }
+    let timeout = limits.timeout(phase);
+    let capture = Arc::new(Capture {
+        pgid,
+        deadline: Instant::now() + timeout,
+        max_bytes: limits.max_output_bytes,
+        written: AtomicUsize::new(0),
+        truncated: AtomicBool::new(false),
+    });
+
+    let stdout = match merged {
+        Some(reader) => capture.read_in_background(reader, on_line.clone()),
+        None => capture.read_in_background(
+            child.stdout.take().expect("stdout is piped"),
+            on_line.clone(),
+        ),
+    };
+    let stderr = child
+        .stderr
+        .take()
+        .map(|reader| capture.read_in_background(reader, on_line));
+
+    // Written in the background so a command that doesn't read all of it
+    // can't block us, a broken pipe only means it exited before reading it
+    if let (Some(mut stdin), Some(input)) = (child.stdin.take(), input) {
+        thread::spawn(move || stdin.write_all(&input));
+    }
+
+    // The child is reaped by `wait4` which also returns its resource usage
+    drop(child);
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || sender.send(wait_exited(pgid)));
+
+    let timed_out = match receiver.recv_timeout(timeout) {
+        Ok(exited) => {
+            exited?;
+            false
+        }
+        Err(_) => {
+            kill_group(pgid);
+            receiver
+                .recv()
+                .map_err(|_| io::Error::other("process waiter stopped"))??;
+            true
+        }
+    };
+
+    // Anything the process left running in the background holds the pipes.
+    // The leader isn't reaped yet so the group's id can't have been reused
+    kill_group(pgid);
+    limits.cancellation.unregister(pgid);
+    let (status, usage) = wait_with_usage(pgid)?;
+
+    let stdout = join_reader(stdout);
+    let stderr = stderr.map(join_reader).unwrap_or_default();
+
+    if limits.cancellation.is_cancelled() {
+        return Err(cancelled());
+    }
+
+    let limit_exceeded = if timed_out {
+        Some(LimitExceeded::TimedOut {
+            phase,
+            limit_ms: timeout.as_millis() as u64,
+        })
+    } else if capture.truncated.load(Ordering::SeqCst) {
+        Some(LimitExceeded::OutputTruncated {
+            phase,
+            limit_bytes: limits.max_output_bytes,
+        })
+    } else if phase == Phase::Run {
+        limits
+            .resources
+            .exceeded(&status, usage.cpu_time, &[&stdout, &stderr])
+            .map(|resource| LimitExceeded::ResourceLimit {
+                phase,
+                resource,
+                limit: limits.resources.limit(resource).unwrap_or_default(),
+            })
+    } else {
+        None
+    };
+
+    Ok(ProcessOutput {
+        status,
+        stdout,
+        stderr,
+        limit_exceeded,
+        peak_rss_bytes: usage.peak_rss_bytes,
+    })
+}
+
+/// Waits for the process to exit without reaping it
+fn wait_exited(pid: i32) -> io::Result<()> {
+    // SAFETY: siginfo_t is plain data, all zeroes is a valid value
+    let mut info: libc::siginfo_t = unsafe { mem::zeroed() };
+
+    // SAFETY: the pointer is to a local that outlives the call
+    while unsafe {
+        libc::waitid(
+            libc::P_PID,
+            pid as libc::id_t,
+            &mut info,
+            libc::WEXITED | libc::WNOWAIT,
+        )
+    } < 0
+    {
+        let e = io::Error::last_os_error();
+        if e.kind() != io::ErrorKind::Interrupted {
+            return Err(e);
+        }
+    }
+
+    Ok(())
+}
+
+/// Resource usage of a reaped process, from `wait4`
+struct Usage {
+    peak_rss_bytes: u64,
+    /// User and system CPU time
+    cpu_time: Duration,
+}
+
+/// Waits for the process, returning its exit status and resource usage
+fn wait_with_usage(pid: i32) -> io::Result<(ExitStatus, Usage)> {
+    let mut status = 0;
+    // SAFETY: rusage is plain data, all zeroes is a valid value
+    let mut usage: libc::rusage = unsafe { mem::zeroed() };
+
+    // SAFETY: both pointers are to locals that outlive the call
+    while unsafe { libc::wait4(pid, &mut status, 0, &mut usage) } < 0 {
+        let e = io::Error::last_os_error();
+        if e.kind() != io::ErrorKind::Interrupted {
+            return Err(e);
+        }
+    }
+
+    let cpu_time = [usage.ru_utime, usage.ru_stime]
+        .iter()
+        .map(|time| {
+            Duration::from_secs(time.tv_sec.max(0) as u64)
+                + Duration::from_micros(time.tv_usec.max(0) as u64)
+        })
+        .sum();
+
+    // `ru_maxrss` is in kilobytes
+    Ok((
+        ExitStatus::from_raw(status),
+        Usage {
+            peak_rss_bytes: usage.ru_maxrss.max(0) as u64 * 1024,
+            cpu_time,
+        },
+    ))
+}
+
+type OnLine = Arc<dyn Fn(&str) + Send + Sync>;
+
+/// Output budget shared by the stdout and stderr readers of one process
+struct Capture {
+    pgid: i32,
+    /// When the readers stop waiting for output, so a process outside the
+    /// group holding the pipes can't keep them, and the joins, running
+    deadline: Instant,
+    max_bytes: usize,
+    written: AtomicUsize,
+    truncated: AtomicBool,
+}
+
+impl Capture {
+    fn read_in_background(
+        self: &Arc<Self>,
+        mut reader: impl Read + AsFd + Send + 'static,
+        on_line: Option<OnLine>,
+    ) -> JoinHandle<Vec<u8>> {
+        let capture = Arc::clone(self);
+
+        thread::spawn(move || {
+            let mut output = Vec::new();
+            let mut buf = [0; 8192];
+            // Start of the line that isn't complete yet
+            let mut line_start = 0;
+
+            loop {
+                let wait = capture.deadline.saturating_duration_since(Instant::now());
+                match wait_readable(reader.as_fd(), wait) {
+                    Ok(true) => {}
+                    Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                    Ok(false) | Err(_) => break,
+                }
+
+                let n = match reader.read(&mut buf) {
+                    Ok(0) => break,
+                    Ok(n) => n,
+                    Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                    Err(_) => break,
+                };
+
+                let before = capture.written.fetch_add(n, Ordering::SeqCst);
+                let keep = capture.max_bytes.saturating_sub(before).min(n);
+                let read_start = output.len();
+                output.extend_from_slice(&buf[..keep]);
+
+                if let Some(on_line) = &on_line {
+                    for end in read_start..output.len() {
+                        if output[end] == b'\n' {
+                            on_line(&String::from_utf8_lossy(&output[line_start..end]));
+                            line_start = end + 1;
+                        }
+                    }
+                }
+
+                if keep < n && !capture.truncated.swap(true, Ordering::SeqCst) {
+                    kill_group(capture.pgid);
+                }
+                // Past the deadline only what fits in the budget is still read
+                if keep < n && Instant::now() >= capture.deadline {
+                    break;
+                }
+            }
+
+            if let Some(on_line) = &on_line {
+                if line_start < output.len() {
+                    on_line(&String::from_utf8_lossy(&output[line_start..]));
+                }
+            }
+
+            output
+        })
+    }
+}
+
+/// Waits up to `timeout` for the pipe to have output or be closed
+fn wait_readable(fd: BorrowedFd<'_>, timeout: Duration) -> io::Result<bool> {
+    let mut poll_fd = libc::pollfd {
+        fd: fd.as_raw_fd(),
+        events: libc::POLLIN,
+        revents: 0,
+    };
+    let timeout_ms = timeout.as_millis().min(libc::c_int::MAX as u128) as libc::c_int;
+
+    // SAFETY: the pointer is to a local that outlives the call, and the fd is
+    // borrowed from the open pipe
+    match unsafe { libc::poll(&mut poll_fd, 1, timeout_ms) } {
+        -1 => Err(io::Error::last_os_error()),
+        ready => Ok(ready > 0),
+    }
+}
+
+fn join_reader(reader: JoinHandle<Vec<u8>>) -> String {
+    let output = reader.join().unwrap_or_default();
+    String::from_utf8_lossy(&output).to_string()
+}
+
+fn kill_group(pgid: i32) {
+    // SAFETY: sending a signal has no memory safety requirements, the group
+    // may already be gone in which case this fails with ESRCH
+    unsafe {
+        libc::killpg(pgid, libc::SIGKILL);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn limits(timeout: Duration, max_output_bytes: usize) -> Limits {
+        Limits {
+            compile_timeout: timeout,
+            run_timeout: timeout,
+            max_output_bytes,
+            ..Limits::default()
+        }
+    }
+
+    #[test]
+    fn test_merges_output() {
+        let output = run_merged(
+            Command::new("sh").args(["-c", "echo out; echo err >&2"]),
+            Phase::Run,
+            &Limits::default(),
+        )
+        .unwrap();
+
+        assert!(output.success());
+        assert_eq!(output.stdout, "out\nerr\n");
+        assert!(output.peak_rss_bytes > 0);
+    }
+
+    #[test]
+    fn test_writes_input_to_stdin() {
+        let output = run_with_input(
+            Command::new("tr").args(["a-z", "A-Z"]),
+            b"input",
+            Phase::Run,
+            &Limits::default(),
+        )
+        .unwrap();
+
+        assert_eq!(output.stdout, "INPUT");
+    }
+
+    #[test]
+    fn test_streams_lines() {
+        let lines = Arc::new(Mutex::new(Vec::new()));
+        let streamed = Arc::clone(&lines);
+
+        let output = run_streaming(
+            Command::new("sh").args(["-c", "echo one; echo two; printf three"]),
+            Phase::Run,
+            &Limits::default(),
+            move |line| streamed.lock().unwrap().push(line.to_string()),
+        )
+        .unwrap();
+
+        assert_eq!(output.stdout, "one\ntwo\nthree");
+        assert_eq!(*lines.lock().unwrap(), ["one", "two", "three"]);
+    }
+
+    #[test]
+    fn test_kills_process_group_on_timeout() {
+        let output = run(
+            Command::new("sh").args(["-c", "sleep 30 & sleep 30"]),
+            Phase::Run,
+            &limits(Duration::from_millis(200), 1024),
+        )
+        .unwrap();
+
+        assert_eq!(
+            output.limit_exceeded,
+            Some(LimitExceeded::TimedOut {
+                phase: Phase::Run,
+                limit_ms: 200,
+            })
+        );
+
+        // Something that left the group keeps the pipes open until the deadline
+        let started = Instant::now();
+        let output = run(
+            Command::new("sh").args(["-c", "setsid sleep 10 & echo done"]),
+            Phase::Run,
+            &limits(Duration::from_millis(500), 1024),
+        )
+        .unwrap();
+
+        assert!(started.elapsed() < Duration::from_secs(5));
+        assert_eq!(output.stdout, "done\n");
+        assert!(output.success());
+    }
+
+    #[test]
+    fn test_truncates_output() {
+        let output = run(
+            Command::new("sh").args(["-c", "yes"]),
+            Phase::Run,
+            &limits(Duration::from_secs(10), 1024),
+        )
+        .unwrap();
+
+        assert_eq!(output.stdout.len(), 1024);
+        assert_eq!(
+            output.limit_exceeded,
+            Some(LimitExceeded::OutputTruncated {
+                phase: Phase::Run,
+                limit_bytes: 1024,
+            })
+        );
+    }
+
+    #[test]
+    fn test_cancels_running_command() {
+        let limits = Limits::default();
+        let cancellation = limits.cancellation.clone();
+
+        let cancel = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(200));
+            cancellation.cancel();
+        });
+        let result = run(Command::new("sleep").arg("30"), Phase::Run, &limits);
+        cancel.join().unwrap();
+
+        assert_eq!(result.unwrap_err().kind(), io::ErrorKind::Interrupted);
+        assert!(run(&mut Command::new("true"), Phase::Run, &limits).is_err());
+    }
+
+    #[test]
+    fn test_reports_resource_limit() {
+        let dir = tempfile::tempdir().unwrap();
+        let limits = Limits {
+            resources: ResourceLimits {
+                file_size_bytes: Some(4096),
+                ..ResourceLimits::default()
+            },
+            ..Limits::default()
+        };
+
+        let output = run(
+            Command::new("sh")
+                .args(["-c", "exec head -c 8192 /dev/zero > out"])
+                .current_dir(dir.path()),
+            Phase::Run,
+            &limits,
+        )
+        .unwrap();
+
+        assert_eq!(
+            output.limit_exceeded,
+            Some(LimitExceeded::ResourceLimit {
+                phase: Phase::Run,
+                resource: Resource::FileSize,
+                limit: 4096,
+            })
+        );
+        assert_eq!(
+            output.limit_exceeded.unwrap().to_string(),
+            "File size limit of 4096 bytes exceeded"
+        );
+
+        // What the code prints doesn't make it a limit
+        let output = run(
+            Command::new("sh").args([
+                "-c",
+                "echo SIGXCPU Too many open files; echo 'memory allocation of 8 bytes failed' >&2; exit 1",
+            ]),
+            Phase::Run,
+            &limits,
+        )
+        .unwrap();
+        assert_eq!(output.limit_exceeded, None);
+
+        // Neither is a SIGKILL before the CPU time was used up
+        let output = run(
+            Command::new("sh").args(["-c", "kill -KILL $$"]),
+            Phase::Run,
+            &limits,
+        )
+        .unwrap();
+        assert_eq!(output.status.signal(), Some(libc::SIGKILL));
+        assert_eq!(output.limit_exceeded, None);
+    }
+}
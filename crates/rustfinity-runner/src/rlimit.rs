@@ -0,0 +1,184 @@
+use std::{
+    fmt, io,
+    os::unix::process::{CommandExt, ExitStatusExt},
+    process::{Command, ExitStatus},
+    time::Duration,
+};
+
+use serde::Serialize;
+
+/// `setrlimit` limits of the commands that run the submitted code, `None`
+/// leaves the limit as it is
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct ResourceLimits {
+    pub cpu_seconds: Option<u64>,
+    /// Address space, the memory the process can map
+    pub memory_bytes: Option<u64>,
+    /// Size of the files the process can write
+    pub file_size_bytes: Option<u64>,
+    /// Processes and threads of the user the process runs as
+    pub processes: Option<u64>,
+    pub open_files: Option<u64>,
+}
+
+impl Default for ResourceLimits {
+    fn default() -> Self {
+        Self {
+            cpu_seconds: Some(10),
+            memory_bytes: Some(2 * 1024 * 1024 * 1024),
+            file_size_bytes: Some(64 * 1024 * 1024),
+            processes: Some(1024),
+            open_files: Some(1024),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Resource {
+    CpuTime,
+    Memory,
+    FileSize,
+    Processes,
+    OpenFiles,
+}
+
+impl Resource {
+    /// Describes the limit, e.g. `CPU time limit of 10s`
+    pub fn describe(&self, limit: u64) -> String {
+        match self {
+            Resource::CpuTime => format!("CPU time limit of {}s", limit),
+            Resource::Memory => format!("Memory limit of {}", Bytes(limit)),
+            Resource::FileSize => format!("File size limit of {}", Bytes(limit)),
+            Resource::Processes => format!("Process limit of {}", limit),
+            Resource::OpenFiles => format!("Open files limit of {}", limit),
+        }
+    }
+}
+
+struct Bytes(u64);
+
+impl fmt::Display for Bytes {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        const MIB: u64 = 1024 * 1024;
+
+        if self.0 >= MIB && self.0.is_multiple_of(MIB) {
+            write!(f, "{} MiB", self.0 / MIB)
+        } else {
+            write!(f, "{} bytes", self.0)
+        }
+    }
+}
+
+impl ResourceLimits {
+    pub fn limit(&self, resource: Resource) -> Option<u64> {
+        match resource {
+            Resource::CpuTime => self.cpu_seconds,
+            Resource::Memory => self.memory_bytes,
+            Resource::FileSize => self.file_size_bytes,
+            Resource::Processes => self.processes,
+            Resource::OpenFiles => self.open_files,
+        }
+    }
+
+    /// Sets the limits in the command's process before it runs the program,
+    /// they're inherited by everything it spawns
+    pub fn apply(&self, command: &mut Command) {
+        let limits = *self;
+
+        // SAFETY: the closure runs between fork and exec, it only calls
+        // setrlimit which is async-signal-safe and doesn't allocate
+        unsafe {
+            command.pre_exec(move || limits.set());
+        }
+    }
+
+    fn set(&self) -> io::Result<()> {
+        // The CPU limit sends SIGXCPU, the hard limit a second later SIGKILL
+        // in case the signal is ignored
+        if let Some(seconds) = self.cpu_seconds {
+            set_rlimit(libc::RLIMIT_CPU, seconds, seconds + 1)?;
+        }
+        if let Some(bytes) = self.memory_bytes {
+            set_rlimit(libc::RLIMIT_AS, bytes, bytes)?;
+        }
+        if let Some(bytes) = self.file_size_bytes {
+            set_rlimit(libc::RLIMIT_FSIZE, bytes, bytes)?;
+        }
+        if let Some(processes) = self.processes {
+            set_rlimit(libc::RLIMIT_NPROC, processes, processes)?;
+        }
+        if let Some(files) = self.open_files {
+            set_rlimit(libc::RLIMIT_NOFILE, files, files)?;
+        }
+
+        Ok(())
+    }
+
+    /// The limit a process hit, from the signal that killed it: the CPU
+    /// limit's SIGXCPU, or SIGKILL at its hard limit once the process used
+    /// `cpu_time`, and the file size limit's SIGXFSZ. An allocation failing at
+    /// the memory limit aborts after Rust's `memory allocation of N bytes
+    /// failed`. Any other SIGKILL is a plain signal death, the process and
+    /// open files limits only make calls fail, they aren't reported
+    pub fn exceeded(
+        &self,
+        status: &ExitStatus,
+        cpu_time: Duration,
+        outputs: &[&str],
+    ) -> Option<Resource> {
+        let resource = match status.signal()? {
+            libc::SIGXCPU => Resource::CpuTime,
+            libc::SIGKILL
+                if self
+                    .cpu_seconds
+                    .is_some_and(|seconds| cpu_time >= Duration::from_secs(seconds)) =>
+            {
+                Resource::CpuTime
+            }
+            libc::SIGXFSZ => Resource::FileSize,
+            libc::SIGABRT if outputs.iter().any(|output| ends_with_alloc_failure(output)) => {
+                Resource::Memory
+            }
+            _ => return None,
+        };
+
+        self.limit(resource).map(|_| resource)
+    }
+}
+
+/// Whether the last line of the output, before the backtrace printed with
+/// `RUST_BACKTRACE`, is the allocation error
+fn ends_with_alloc_failure(output: &str) -> bool {
+    output
+        .lines()
+        .rev()
+        .find(|line| {
+            !line.trim().is_empty()
+                && !line.starts_with(' ')
+                && *line != "stack backtrace:"
+                && !line.starts_with("note: Some details are omitted")
+        })
+        .and_then(|line| line.strip_prefix("memory allocation of "))
+        .and_then(|line| line.strip_suffix(" bytes failed"))
+        .is_some_and(|bytes| !bytes.is_empty() && bytes.chars().all(|c| c.is_ascii_digit()))
+}
+
+#[cfg(all(target_os = "linux", target_env = "gnu"))]
+type RlimitResource = libc::__rlimit_resource_t;
+#[cfg(not(all(target_os = "linux", target_env = "gnu")))]
+type RlimitResource = libc::c_int;
+
+fn set_rlimit(resource: RlimitResource, soft: u64, hard: u64) -> io::Result<()> {
+    let rlimit = libc::rlimit {
+        rlim_cur: soft as libc::rlim_t,
+        rlim_max: hard as libc::rlim_t,
+    };
+
+    // SAFETY: rlimit is a valid pointer for the duration of the call
+    if unsafe { libc::setrlimit(resource, &rlimit) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(())
+}
@@ -0,0 +1,444 @@
+use std::{
+    ffi::{CStr, CString},
+    io, mem,
+    os::unix::{ffi::OsStrExt, process::CommandExt},
+    path::Path,
+    process::Command,
+};
+
+use anyhow::{bail, Context};
+use serde::Serialize;
+
+use crate::process::{run, Limits, Phase};
+
+/// How the commands that run the submitted code are isolated, cargo's
+/// compile step always runs as it is
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+#[serde(rename_all = "lowercase")]
+pub enum Sandbox {
+    /// Only the resource limits, for a runner that's already in a container
+    #[default]
+    None,
+    /// User, network, PID and mount namespaces with a seccomp filter. The
+    /// file system is read-only except the crate's directory and `/tmp`
+    Namespaces,
+}
+
+impl Sandbox {
+    /// Makes the command enter the sandbox before it runs the program
+    pub fn apply(&self, command: &mut Command) -> io::Result<()> {
+        if *self == Sandbox::None {
+            return Ok(());
+        }
+
+        let jail = Jail::new(command.get_current_dir())?;
+
+        // SAFETY: the closure runs between fork and exec, `Jail::enter` only
+        // makes syscalls with buffers prepared here and doesn't allocate
+        unsafe {
+            command.pre_exec(move || jail.enter());
+        }
+
+        Ok(())
+    }
+
+    /// Runs a command in the sandbox to check that the host supports it
+    pub fn check(&self) -> anyhow::Result<()> {
+        if *self == Sandbox::None {
+            return Ok(());
+        }
+
+        let limits = Limits {
+            sandbox: *self,
+            ..Limits::default()
+        };
+        let output = run(&mut Command::new("true"), Phase::Run, &limits).context(
+            "Failed to create the namespaces, unprivileged user namespaces may be turned off \
+             (`user.max_user_namespaces`, `kernel.apparmor_restrict_unprivileged_userns`)",
+        )?;
+
+        if !output.success() {
+            bail!("A command in the sandbox failed with {}", output.status);
+        }
+
+        Ok(())
+    }
+}
+
+/// Everything the forked process needs to enter the sandbox, prepared before
+/// the fork since it can't allocate
+struct Jail {
+    uid_map: Vec<u8>,
+    gid_map: Vec<u8>,
+    /// The command's directory, it stays writable
+    scratch: Option<CString>,
+    /// `/tmp` is a new tmpfs unless the scratch directory is in it
+    private_tmp: bool,
+    filter: Vec<libc::sock_filter>,
+}
+
+const NAMESPACES: libc::c_int = libc::CLONE_NEWUSER
+    | libc::CLONE_NEWNS
+    | libc::CLONE_NEWNET
+    | libc::CLONE_NEWPID
+    | libc::CLONE_NEWIPC
+    | libc::CLONE_NEWUTS;
+
+impl Jail {
+    fn new(current_dir: Option<&Path>) -> io::Result<Self> {
+        let scratch = current_dir.map(Path::canonicalize).transpose()?;
+
+        // SAFETY: getuid and getgid can't fail
+        let (uid, gid) = unsafe { (libc::getuid(), libc::getgid()) };
+
+        Ok(Self {
+            uid_map: format!("{} {} 1", uid, uid).into_bytes(),
+            gid_map: format!("{} {} 1", gid, gid).into_bytes(),
+            private_tmp: !scratch
+                .as_ref()
+                .is_some_and(|scratch| scratch.starts_with("/tmp")),
+            scratch: scratch
+                .map(|scratch| CString::new(scratch.as_os_str().as_bytes()))
+                .transpose()?,
+            filter: seccomp_filter()?,
+        })
+    }
+
+    /// Runs in the forked process. The process creating a PID namespace isn't
+    /// in it, so it forks the namespace's init which forks the process that
+    /// runs the command, and they exit with its status
+    fn enter(&self) -> io::Result<()> {
+        // SAFETY: every pointer passed below is to a buffer of the jail or a
+        // local that outlives the call
+        unsafe {
+            check(libc::unshare(NAMESPACES))?;
+            write_file(c"/proc/self/setgroups", b"deny")?;
+            write_file(c"/proc/self/uid_map", &self.uid_map)?;
+            write_file(c"/proc/self/gid_map", &self.gid_map)?;
+            self.mount()?;
+
+            let mut pipe = [0; 2];
+            check(libc::pipe2(pipe.as_mut_ptr(), libc::O_CLOEXEC))?;
+            let [status_reader, status_writer] = pipe;
+
+            let init = check(libc::fork())?;
+            if init > 0 {
+                close_fds_except(status_reader);
+                let mut status = 0;
+                let read = libc::read(
+                    status_reader,
+                    &mut status as *mut libc::c_int as *mut libc::c_void,
+                    mem::size_of::<libc::c_int>(),
+                );
+                let init_status = wait(init);
+                exit_with(if read == mem::size_of::<libc::c_int>() as isize {
+                    status
+                } else {
+                    init_status
+                });
+            }
+
+            check(libc::mount(
+                c"proc".as_ptr(),
+                c"/proc".as_ptr(),
+                c"proc".as_ptr(),
+                libc::MS_NOSUID | libc::MS_NODEV | libc::MS_NOEXEC,
+                std::ptr::null(),
+            ))?;
+
+            let command = check(libc::fork())?;
+            if command > 0 {
+                close_fds_except(status_writer);
+                let status = wait(command);
+                libc::write(
+                    status_writer,
+                    &status as *const libc::c_int as *const libc::c_void,
+                    mem::size_of::<libc::c_int>(),
+                );
+                libc::_exit(0);
+            }
+
+            check(libc::prctl(libc::PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0))?;
+            let program = libc::sock_fprog {
+                len: self.filter.len() as libc::c_ushort,
+                filter: self.filter.as_ptr() as *mut libc::sock_filter,
+            };
+            check(libc::prctl(
+                libc::PR_SET_SECCOMP,
+                libc::SECCOMP_MODE_FILTER,
+                &program as *const libc::sock_fprog,
+            ))?;
+        }
+
+        Ok(())
+    }
+
+    /// Makes every mount read-only and private, then mounts the writable
+    /// scratch directory and `/tmp` on top
+    unsafe fn mount(&self) -> io::Result<()> {
+        let read_only = libc::mount_attr {
+            attr_set: libc::MOUNT_ATTR_RDONLY,
+            attr_clr: 0,
+            propagation: libc::MS_PRIVATE,
+            userns_fd: 0,
+        };
+        mount_setattr(c"/", libc::AT_RECURSIVE, &read_only)?;
+
+        if self.private_tmp {
+            check(libc::mount(
+                c"tmpfs".as_ptr(),
+                c"/tmp".as_ptr(),
+                c"tmpfs".as_ptr(),
+                libc::MS_NOSUID | libc::MS_NODEV,
+                c"size=256m,mode=1777".as_ptr() as *const libc::c_void,
+            ))?;
+        }
+
+        if let Some(scratch) = &self.scratch {
+            check(libc::mount(
+                scratch.as_ptr(),
+                scratch.as_ptr(),
+                std::ptr::null(),
+                libc::MS_BIND,
+                std::ptr::null(),
+            ))?;
+
+            let writable = libc::mount_attr {
+                attr_set: 0,
+                attr_clr: libc::MOUNT_ATTR_RDONLY,
+                propagation: 0,
+                userns_fd: 0,
+            };
+            mount_setattr(scratch, 0, &writable)?;
+
+            // The working directory is still the one of the read-only mount
+            check(libc::chdir(scratch.as_ptr()))?;
+        }
+
+        Ok(())
+    }
+}
+
+fn check<T: Default + PartialOrd>(result: T) -> io::Result<T> {
+    if result < T::default() {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(result)
+}
+
+unsafe fn write_file(path: &CStr, content: &[u8]) -> io::Result<()> {
+    let fd = check(libc::open(path.as_ptr(), libc::O_WRONLY | libc::O_CLOEXEC))?;
+    let written = libc::write(fd, content.as_ptr() as *const libc::c_void, content.len());
+    libc::close(fd);
+
+    if written != content.len() as isize {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(())
+}
+
+unsafe fn mount_setattr(
+    path: &CStr,
+    flags: libc::c_int,
+    attr: &libc::mount_attr,
+) -> io::Result<()> {
+    check(libc::syscall(
+        libc::SYS_mount_setattr,
+        libc::AT_FDCWD,
+        path.as_ptr(),
+        flags,
+        attr as *const libc::mount_attr,
+        mem::size_of::<libc::mount_attr>(),
+    ))?;
+
+    Ok(())
+}
+
+/// Closes the descriptors the waiting processes inherited, cargo or the
+/// runner would wait for the pipes they hold to be closed
+unsafe fn close_fds_except(keep: libc::c_int) {
+    for (first, last) in [(3, keep - 1), (keep + 1, libc::c_int::MAX)] {
+        if first <= last {
+            libc::syscall(libc::SYS_close_range, first, last, 0);
+        }
+    }
+}
+
+unsafe fn wait(pid: libc::pid_t) -> libc::c_int {
+    let mut status = 0;
+
+    while libc::waitpid(pid, &mut status, 0) < 0 {
+        if *libc::__errno_location() != libc::EINTR {
+            return 1 << 8;
+        }
+    }
+
+    status
+}
+
+/// Exits like the process with the wait status did, killed by the same signal
+unsafe fn exit_with(status: libc::c_int) -> ! {
+    if libc::WIFSIGNALED(status) {
+        let signal = libc::WTERMSIG(status);
+        let mut signals = mem::zeroed();
+        libc::sigemptyset(&mut signals);
+        libc::sigaddset(&mut signals, signal);
+        libc::signal(signal, libc::SIG_DFL);
+        libc::sigprocmask(libc::SIG_UNBLOCK, &signals, std::ptr::null_mut());
+        libc::kill(libc::getpid(), signal);
+        libc::_exit(128 + signal);
+    }
+
+    libc::_exit(libc::WEXITSTATUS(status))
+}
+
+#[cfg(target_arch = "x86_64")]
+const AUDIT_ARCH: Option<u32> = Some(0xc000_003e);
+#[cfg(target_arch = "aarch64")]
+const AUDIT_ARCH: Option<u32> = Some(0xc000_00b7);
+#[cfg(not(any(target_arch = "x86_64", target_arch = "aarch64")))]
+const AUDIT_ARCH: Option<u32> = None;
+
+/// Syscalls that fail with `EPERM`, the ones that change the sandbox, the
+/// kernel or other processes
+const DENIED_SYSCALLS: [libc::c_long; 33] = [
+    libc::SYS_ptrace,
+    libc::SYS_process_vm_readv,
+    libc::SYS_process_vm_writev,
+    libc::SYS_mount,
+    libc::SYS_umount2,
+    libc::SYS_mount_setattr,
+    libc::SYS_move_mount,
+    libc::SYS_open_tree,
+    libc::SYS_fsopen,
+    libc::SYS_fsconfig,
+    libc::SYS_fsmount,
+    libc::SYS_fspick,
+    libc::SYS_pivot_root,
+    libc::SYS_chroot,
+    libc::SYS_unshare,
+    libc::SYS_setns,
+    libc::SYS_reboot,
+    libc::SYS_kexec_load,
+    libc::SYS_kexec_file_load,
+    libc::SYS_init_module,
+    libc::SYS_finit_module,
+    libc::SYS_delete_module,
+    libc::SYS_bpf,
+    libc::SYS_perf_event_open,
+    libc::SYS_userfaultfd,
+    libc::SYS_keyctl,
+    libc::SYS_add_key,
+    libc::SYS_request_key,
+    libc::SYS_swapon,
+    libc::SYS_swapoff,
+    libc::SYS_acct,
+    libc::SYS_open_by_handle_at,
+    libc::SYS_syslog,
+];
+
+/// Offsets in `struct seccomp_data`
+const SYSCALL_NR: u32 = 0;
+const SYSCALL_ARCH: u32 = 4;
+/// Low half of the first argument, both architectures are little endian
+const SYSCALL_ARG0: u32 = 16;
+
+fn seccomp_filter() -> io::Result<Vec<libc::sock_filter>> {
+    let Some(arch) = AUDIT_ARCH else {
+        return Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "the seccomp filter isn't available on this architecture",
+        ));
+    };
+
+    let statement = |code: u32, k| libc::sock_filter {
+        code: code as u16,
+        jt: 0,
+        jf: 0,
+        k,
+    };
+    let load = |offset| statement(libc::BPF_LD | libc::BPF_W | libc::BPF_ABS, offset);
+    let ret = |action| statement(libc::BPF_RET | libc::BPF_K, action);
+    let jump = |op: u32, k, jt, jf| libc::sock_filter {
+        code: (libc::BPF_JMP | op | libc::BPF_K) as u16,
+        jt,
+        jf,
+        k,
+    };
+    let errno = |errno: libc::c_int| libc::SECCOMP_RET_ERRNO | errno as u32;
+
+    let mut filter = vec![
+        load(SYSCALL_ARCH),
+        jump(libc::BPF_JEQ, arch, 1, 0),
+        ret(libc::SECCOMP_RET_KILL_PROCESS),
+        load(SYSCALL_NR),
+    ];
+
+    // x32 syscalls of x86_64, the numbers have the high bit set
+    #[cfg(target_arch = "x86_64")]
+    filter.extend([
+        jump(libc::BPF_JGE, 0x4000_0000, 0, 1),
+        ret(libc::SECCOMP_RET_KILL_PROCESS),
+    ]);
+
+    for syscall in DENIED_SYSCALLS {
+        filter.extend([
+            jump(libc::BPF_JEQ, syscall as u32, 0, 1),
+            ret(errno(libc::EPERM)),
+        ]);
+    }
+
+    // clone3 passes its flags in a struct the filter can't read, libc falls
+    // back to clone when it isn't there
+    filter.extend([
+        jump(libc::BPF_JEQ, libc::SYS_clone3 as u32, 0, 1),
+        ret(errno(libc::ENOSYS)),
+        jump(libc::BPF_JEQ, libc::SYS_clone as u32, 0, 3),
+        load(SYSCALL_ARG0),
+        jump(libc::BPF_JSET, NAMESPACES as u32, 0, 1),
+        ret(errno(libc::EPERM)),
+        ret(libc::SECCOMP_RET_ALLOW),
+    ]);
+
+    Ok(filter)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_isolates_the_command() {
+        if let Err(e) = Sandbox::Namespaces.check() {
+            eprintln!("Skipped, the sandbox isn't available: {:#}", e);
+            return;
+        }
+
+        let dir = tempfile::tempdir_in(env!("CARGO_MANIFEST_DIR")).unwrap();
+        let limits = Limits {
+            sandbox: Sandbox::Namespaces,
+            ..Limits::default()
+        };
+
+        let output = run(
+            Command::new("sh")
+                .args([
+                    "-c",
+                    "echo $$; touch scratch && echo scratch; touch /tmp/tmp && echo tmp; \
+                     touch ../escape || echo read-only; unshare -U true || echo denied",
+                ])
+                .current_dir(dir.path()),
+            Phase::Run,
+            &limits,
+        )
+        .unwrap();
+
+        assert!(output.success(), "{}", output.stderr);
+        assert_eq!(output.stdout, "2\nscratch\ntmp\nread-only\ndenied\n");
+        assert!(!dir.path().join("../escape").exists());
+        assert!(!Path::new("/tmp/tmp").exists());
+    }
+}
@@ -0,0 +1,308 @@
+use std::path::Path;
+
+use regex::Regex;
+
+use crate::libtest::{backtrace_frames, is_panic_line, user_frames};
+
+/// Cargo's lines about the build rather than the code, dropped unless verbose
+const BUILD_CHATTER: [&str; 12] = [
+    "Compiling ",
+    "Checking ",
+    "Finished `",
+    "Running ",
+    "Executable ",
+    "Blocking waiting for file lock",
+    "Downloading ",
+    "Downloaded ",
+    "Updating ",
+    "Locking ",
+    "Adding ",
+    "Fresh ",
+];
+
+/// Rewrites the output of cargo and the tests for the learner: paths are
+/// relative to the crate and test binaries are named by their source file,
+/// colours are stripped, the build chatter is dropped unless `verbose`, and
+/// failed tests start with their panic message
+pub struct Sanitizer<'a> {
+    root: &'a Path,
+    verbose: bool,
+    separators: bool,
+    user_files: Option<&'a [String]>,
+}
+
+impl<'a> Sanitizer<'a> {
+    /// `root` is the directory the crate was built in
+    pub fn new(root: &'a Path, verbose: bool) -> Self {
+        Self {
+            root,
+            verbose,
+            separators: true,
+            user_files: None,
+        }
+    }
+
+    /// Cuts the backtraces of the failed tests down to the frames in the
+    /// submitted `files`
+    pub fn with_user_files(mut self, files: &'a [String]) -> Self {
+        self.user_files = Some(files);
+        self
+    }
+
+    /// Drops the `---` lines separating the runner's own sections, e.g. the
+    /// benchmark, a blank line separates them instead. Only for output where
+    /// the learner's code can't print one, it would be dropped too
+    pub fn without_separators(mut self) -> Self {
+        self.separators = false;
+        self
+    }
+
+    pub fn sanitize(&self, output: &str) -> String {
+        let mut output = strip_colors(output);
+
+        // Cargo names the binaries it runs next to their source file, e.g.
+        // `Running tests/tests.rs (target/debug/deps/tests-e375644f618b0818)`
+        let running = Regex::new(r"Running (?:\S+ )?(\S+) \((.+?)\)").unwrap();
+        let binaries = running
+            .captures_iter(&output)
+            .map(|caps| (caps[2].to_string(), caps[1].to_string()))
+            .collect::<Vec<_>>();
+
+        let root = self.root.display().to_string();
+        output = output
+            .replace(&format!("{}/", root), "")
+            .replace(&root, ".");
+
+        // The verbose output keeps the `Running` lines with both
+        if !self.verbose {
+            for (binary, source) in binaries {
+                let binary = binary
+                    .strip_prefix(&format!("{}/", root))
+                    .unwrap_or(&binary);
+                output = output.replace(binary, &source);
+            }
+        }
+
+        let lines = output
+            .lines()
+            .filter(|line| self.verbose || !is_build_chatter(line))
+            .map(|line| match line {
+                "---" if !self.separators => "",
+                line => line,
+            })
+            .collect::<Vec<_>>();
+
+        without_blank_runs(&with_panics_first(&lines, self.user_files))
+    }
+}
+
+fn is_build_chatter(line: &str) -> bool {
+    let line = line.trim_start();
+
+    BUILD_CHATTER.iter().any(|prefix| line.starts_with(prefix))
+        || line.starts_with("error: test failed, to rerun pass")
+        // The summary of the warnings above, e.g.
+        // warning: `fibonacci` (lib test) generated 1 warning (1 duplicate)
+        || (line.starts_with("warning: `") && line.contains(" generated "))
+        // What an empty test target prints, the library's unit tests
+        || line == "running 0 tests"
+        || line.starts_with("test result: ok. 0 passed; 0 failed; 0 ignored;")
+}
+
+/// Removes ANSI escape sequences, the colours and styles of the output
+pub fn strip_colors(output: &str) -> String {
+    let escape = Regex::new(r"\x1b\[[0-9;?]*[ -/]*[@-~]").unwrap();
+    escape.replace_all(output, "").into_owned()
+}
+
+/// Rewrites the blocks libtest prints for failed tests, e.g.
+/// `---- tests::zero stdout ----`, to start with the test's name and its
+/// panic message and location, followed by the backtrace in `user_files`
+/// and what the test printed
+fn with_panics_first(lines: &[&str], user_files: Option<&[String]>) -> Vec<String> {
+    let header = Regex::new(r"^---- (.+) stdout ----$").unwrap();
+    let location = Regex::new(r"panicked at (.+):$").unwrap();
+
+    let mut rewritten = Vec::new();
+    let mut i = 0;
+
+    while i < lines.len() {
+        let Some(name) = header.captures(lines[i]).map(|caps| caps[1].to_string()) else {
+            rewritten.push(lines[i].to_string());
+            i += 1;
+            continue;
+        };
+
+        let end = (i + 1..lines.len())
+            .find(|&j| lines[j].starts_with("---- ") || lines[j] == "failures:")
+            .unwrap_or(lines.len());
+        let block = &lines[i + 1..end];
+
+        rewritten.push(format!("---- {} ----", name));
+
+        match block.iter().position(|line| is_panic_line(line)) {
+            Some(panic) => {
+                let message_end = (panic + 1..block.len())
+                    .find(|&j| {
+                        block[j].is_empty()
+                            || block[j].starts_with("note: ")
+                            || block[j].starts_with("stack backtrace:")
+                    })
+                    .unwrap_or(block.len());
+
+                rewritten.extend(block[panic + 1..message_end].iter().map(|l| l.to_string()));
+                if let Some(caps) = location.captures(block[panic]) {
+                    rewritten.push(format!("  --> {}", &caps[1]));
+                }
+
+                let mut rest = block[message_end..].to_vec();
+                if let Some(user_files) = user_files {
+                    let frames = user_frames(&backtrace_frames(&rest.join("\n")), user_files);
+                    if !frames.is_empty() {
+                        rewritten.push(String::new());
+                        rewritten.push("Backtrace of your code:".to_string());
+                        rewritten.extend(frames.iter().map(|frame| {
+                            format!(
+                                "  {} at {}:{}:{}",
+                                frame.function, frame.file, frame.line, frame.column
+                            )
+                        }));
+                    }
+                    rest = without_backtrace(&rest);
+                }
+
+                let printed = block[..panic]
+                    .iter()
+                    .skip_while(|line| line.is_empty())
+                    .map(|line| line.to_string())
+                    .collect::<Vec<_>>();
+                if !printed.is_empty() {
+                    rewritten.push(String::new());
+                    rewritten.push("Output:".to_string());
+                    rewritten.extend(printed);
+                }
+                rewritten.push(String::new());
+
+                rewritten.extend(rest.iter().map(|l| l.to_string()));
+            }
+            None => rewritten.extend(block.iter().map(|line| line.to_string())),
+        }
+
+        i = end;
+    }
+
+    rewritten
+}
+
+/// Drops the backtrace and the note about its omitted details
+fn without_backtrace<'l>(lines: &[&'l str]) -> Vec<&'l str> {
+    let mut in_backtrace = false;
+
+    lines
+        .iter()
+        .filter(|line| {
+            if **line == "stack backtrace:" {
+                in_backtrace = true;
+            } else if !line.starts_with(' ') {
+                in_backtrace = false;
+            }
+            !in_backtrace && !line.starts_with("note: Some details are omitted")
+        })
+        .copied()
+        .collect()
+}
+
+/// Joins the lines, with at most one blank line in a row and none first
+fn without_blank_runs(lines: &[String]) -> String {
+    let mut output = String::new();
+    let mut blank = true;
+
+    for line in lines {
+        if line.trim().is_empty() {
+            if !blank {
+                output.push('\n');
+            }
+            blank = true;
+            continue;
+        }
+        output.push_str(line);
+        output.push('\n');
+        blank = false;
+    }
+
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sanitizes_test_output() {
+        let output = "   Compiling fibonacci v0.1.0 (/app/challenges/.run-0)
+    Finished `test` profile [unoptimized + debuginfo] target(s) in 0.25s
+     Running unittests src/lib.rs (target/debug/deps/fibonacci-763dfbe1d84a6954)
+
+running 0 tests
+
+test result: ok. 0 passed; 0 failed; 0 ignored; 0 measured; 0 filtered out; finished in 0.00s
+
+     Running tests/tests.rs (target/debug/deps/tests-e375644f618b0818)
+
+running 1 test
+test tests::zero ... \x1b[31mFAILED\x1b[0m
+
+failures:
+
+---- tests::zero stdout ----
+computing fibonacci(0)
+
+thread 'tests::zero' (1517) panicked at tests/tests.rs:7:9:
+assertion `left == right` failed
+  left: 1
+ right: 0
+note: run with `RUST_BACKTRACE=1` environment variable to display a backtrace
+
+
+failures:
+    tests::zero
+
+test result: FAILED. 0 passed; 1 failed; 0 ignored; 0 measured; 0 filtered out; finished in 0.02s
+
+error: test failed, to rerun pass `--test tests`
+
+Caused by:
+  process didn't exit successfully: `/app/challenges/.run-0/target/debug/deps/tests-e375644f618b0818` (signal: 11, SIGSEGV: invalid memory reference)
+";
+
+        let sanitized = Sanitizer::new(Path::new("/app/challenges/.run-0"), false).sanitize(output);
+
+        assert_eq!(
+            sanitized,
+            "running 1 test
+test tests::zero ... FAILED
+
+failures:
+
+---- tests::zero ----
+assertion `left == right` failed
+  left: 1
+ right: 0
+  --> tests/tests.rs:7:9
+
+Output:
+computing fibonacci(0)
+
+note: run with `RUST_BACKTRACE=1` environment variable to display a backtrace
+
+failures:
+    tests::zero
+
+test result: FAILED. 0 passed; 1 failed; 0 ignored; 0 measured; 0 filtered out; finished in 0.02s
+
+Caused by:
+  process didn't exit successfully: `tests/tests.rs` (signal: 11, SIGSEGV: invalid memory reference)
+"
+        );
+    }
+}
@@ -0,0 +1,242 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use anyhow::Context;
+use serde::Deserialize;
+
+use crate::{
+    error::InvalidJob,
+    verdict::{Score, TestResult, TestStatus},
+};
+
+/// Weights and concept groups of a challenge's tests, from the
+/// `tests/scoring.toml` next to the tests:
+///
+/// ```toml
+/// default_weight = 1
+///
+/// [tests.zero]
+/// weight = 2
+/// group = "base cases"
+/// ```
+///
+/// A test is listed by its full name, e.g. `tests::zero`, or by its name
+/// without the modules. Tests that aren't listed can declare their weight in
+/// their name, `big_input_w3` weighs 3
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Scoring {
+    #[serde(default = "default_weight")]
+    default_weight: u32,
+    #[serde(default)]
+    tests: BTreeMap<String, TestScoring>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct TestScoring {
+    weight: Option<u32>,
+    group: Option<String>,
+}
+
+fn default_weight() -> u32 {
+    1
+}
+
+impl Default for Scoring {
+    fn default() -> Self {
+        Self {
+            default_weight: default_weight(),
+            tests: BTreeMap::new(),
+        }
+    }
+}
+
+impl Scoring {
+    pub fn parse(toml: &str) -> anyhow::Result<Self> {
+        toml::from_str(toml).context(InvalidJob("Invalid scoring manifest".to_string()))
+    }
+
+    /// Whether the weights come from the manifest or the test names rather
+    /// than every test weighing the same
+    pub fn is_weighted(&self, tests: &[TestResult]) -> bool {
+        !self.tests.is_empty()
+            || tests
+                .iter()
+                .any(|test| weight_in_name(&test.name).is_some())
+    }
+
+    pub fn weight(&self, name: &str) -> u32 {
+        self.listed(name)
+            .and_then(|test| test.weight)
+            .or_else(|| weight_in_name(name))
+            .unwrap_or(self.default_weight)
+    }
+
+    /// The weights of the passed tests over the weights of the tests that
+    /// ran and the listed tests that didn't, out of 100. `completed` is false
+    /// when the code didn't compile, a limit was hit or a test binary died,
+    /// which scores zero. A test reported more than once counts once, failed
+    /// if one of its results did
+    pub fn score(&self, tests: &[TestResult], completed: bool) -> Score {
+        let mut statuses = BTreeMap::new();
+        for test in tests {
+            let status = statuses.entry(test.name.as_str()).or_insert(test.status);
+            if test.status == TestStatus::Failed {
+                *status = TestStatus::Failed;
+            }
+        }
+
+        let ran = statuses
+            .iter()
+            .filter(|(_, status)| **status != TestStatus::Ignored);
+
+        // A test the binary never got to counts as failed
+        let not_run = self
+            .tests
+            .iter()
+            .filter(|(key, _)| !statuses.keys().any(|name| matches(key, name)))
+            .map(|(_, test)| u64::from(test.weight.unwrap_or(self.default_weight)))
+            .sum::<u64>();
+
+        let total = not_run
+            + ran
+                .clone()
+                .map(|(name, _)| u64::from(self.weight(name)))
+                .sum::<u64>();
+        let earned = ran
+            .filter(|(_, status)| **status == TestStatus::Passed)
+            .map(|(name, _)| u64::from(self.weight(name)))
+            .sum::<u64>();
+
+        let score = match total {
+            _ if !completed => 0,
+            0 => 0,
+            total => (earned * 100 / total) as u32,
+        };
+
+        // A listed test that didn't run is missed too, e.g. when the code
+        // didn't compile
+        let missed_groups = self
+            .tests
+            .iter()
+            .filter(|(key, _)| {
+                !statuses
+                    .iter()
+                    .any(|(name, status)| matches(key, name) && *status != TestStatus::Failed)
+            })
+            .filter_map(|(_, test)| test.group.clone())
+            .collect::<BTreeSet<_>>()
+            .into_iter()
+            .collect();
+
+        Score {
+            score,
+            earned,
+            total,
+            missed_groups,
+        }
+    }
+
+    fn listed(&self, name: &str) -> Option<&TestScoring> {
+        self.tests.get(name).or_else(|| {
+            self.tests
+                .iter()
+                .find(|(key, _)| matches(key, name))
+                .map(|(_, test)| test)
+        })
+    }
+}
+
+/// A test listed as `key`, by its full name or its name without the modules
+fn matches(key: &str, name: &str) -> bool {
+    key == name || name.rsplit("::").next() == Some(key)
+}
+
+/// `3` for a test named `big_input_w3`
+fn weight_in_name(name: &str) -> Option<u32> {
+    let name = name.rsplit("::").next()?;
+    let (_, weight) = name.rsplit_once("_w")?;
+
+    if weight.is_empty() || !weight.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+    weight.parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test(name: &str, status: TestStatus) -> TestResult {
+        TestResult {
+            name: name.to_string(),
+            status,
+            message: None,
+            backtrace: Vec::new(),
+            duration_ms: None,
+            hidden: false,
+        }
+    }
+
+    #[test]
+    fn test_scores_weighted_tests() {
+        let scoring = Scoring::parse(
+            r#"
+            [tests.zero]
+            weight = 2
+            group = "base cases"
+
+            [tests."tests::recursion"]
+            group = "recursion"
+            "#,
+        )
+        .unwrap();
+
+        let tests = [
+            test("tests::zero", TestStatus::Passed),
+            test("tests::recursion", TestStatus::Failed),
+            test("tests::big_input_w3", TestStatus::Passed),
+            test("tests::slow", TestStatus::Ignored),
+        ];
+
+        assert_eq!(
+            scoring.score(&tests, true),
+            Score {
+                score: 83,
+                earned: 5,
+                total: 6,
+                missed_groups: vec!["recursion".to_string()],
+            }
+        );
+        assert_eq!(
+            scoring.score(&tests, false),
+            Score {
+                score: 0,
+                earned: 5,
+                total: 6,
+                missed_groups: vec!["recursion".to_string()],
+            }
+        );
+        assert_eq!(
+            scoring.score(&[], false).missed_groups,
+            ["base cases", "recursion"]
+        );
+
+        // The binary died before `recursion` finished
+        let score = scoring.score(&tests[..1], true);
+        assert_eq!((score.score, score.earned, score.total), (66, 2, 3));
+
+        // A failed test reported as passed again still failed
+        let mut reported = tests.to_vec();
+        reported.push(test("tests::recursion", TestStatus::Passed));
+        reported.push(test("tests::big_input_w3", TestStatus::Passed));
+        assert_eq!(scoring.score(&reported, true), scoring.score(&tests, true));
+
+        let huge =
+            Scoring::parse("[tests.a]\nweight = 4294967295\n[tests.b]\nweight = 4294967295\n")
+                .unwrap();
+        let tests = [test("a", TestStatus::Passed), test("b", TestStatus::Passed)];
+        assert_eq!(huge.score(&tests, true).score, 100);
+        assert!(Scoring::parse("[tests.zero]\npoints = 2\n").is_err());
+    }
+}
@@ -0,0 +1,389 @@
+use std::{
+    collections::HashMap,
+    convert::Infallible,
+    fs,
+    os::unix::fs::FileTypeExt,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+
+use anyhow::{anyhow, Context};
+use axum::{
+    body::Body,
+    extract::{rejection::JsonRejection, Path, Query, State},
+    http::{header, StatusCode},
+    response::{IntoResponse, Response},
+    routing::{get, post},
+    Json, Router,
+};
+use futures_util::stream;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use tokio::{
+    net::{TcpListener, UnixListener},
+    runtime::Handle,
+    sync::{mpsc, watch, Semaphore},
+};
+
+use crate::{
+    error::{Outcome, RunnerError},
+    events::Events,
+    job::{run_job, Job, JobConfig},
+    process::Cancellation,
+};
+
+/// How long the result of a finished job can be fetched
+const FINISHED_JOB_TTL: Duration = Duration::from_secs(10 * 60);
+
+/// Serves the jobs API on `listen`, a `host:port` address or `unix:<path>`.
+///
+/// - `POST /jobs` queues a job, `?wait=true` responds once it's done and
+///   `?stream=true` streams its progress as newline-delimited JSON
+/// - `GET /jobs/{id}` returns the status of a job and its result when it's done
+/// - `DELETE /jobs/{id}` cancels a job, killing its running command
+/// - `GET /status` returns the number of workers, running and queued jobs and
+///   the sandbox the submitted code runs in
+pub async fn serve(
+    listen: &str,
+    workers: usize,
+    queue_size: usize,
+    config: JobConfig,
+) -> anyhow::Result<()> {
+    let server = Arc::new(Server {
+        jobs: Mutex::new(HashMap::new()),
+        next_id: AtomicU64::new(1),
+        workers: Semaphore::new(workers),
+        n_workers: workers,
+        queue_size,
+        config,
+    });
+
+    let app = Router::new()
+        .route("/jobs", post(submit_job))
+        .route("/jobs/{id}", get(job_status).delete(cancel_job))
+        .route("/status", get(server_status))
+        .with_state(server);
+
+    match listen.strip_prefix("unix:") {
+        Some(path) => {
+            // A socket left behind by a previous server, anything else at
+            // the path is left alone and the bind fails
+            let is_socket =
+                fs::symlink_metadata(path).is_ok_and(|metadata| metadata.file_type().is_socket());
+            if is_socket {
+                fs::remove_file(path)
+                    .with_context(|| format!("Failed to remove the socket {}", path))?;
+            }
+            let listener = UnixListener::bind(path)
+                .with_context(|| format!("Failed to listen on {}", listen))?;
+            axum::serve(listener, app).await?;
+        }
+        None => {
+            let listener = TcpListener::bind(listen)
+                .await
+                .with_context(|| format!("Failed to listen on {}", listen))?;
+            axum::serve(listener, app).await?;
+        }
+    }
+
+    Ok(())
+}
+
+struct Server {
+    jobs: Mutex<HashMap<u64, JobEntry>>,
+    next_id: AtomicU64,
+    /// A job holds a permit while it runs
+    workers: Semaphore,
+    n_workers: usize,
+    queue_size: usize,
+    config: JobConfig,
+}
+
+struct JobEntry {
+    command: &'static str,
+    state: JobState,
+    cancellation: Cancellation,
+    /// Set to true once the job is done
+    done: watch::Sender<bool>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+enum JobState {
+    Queued,
+    Running,
+    /// The command ran, `success` is its exit status
+    Finished {
+        result: Value,
+        success: bool,
+        outcome: Outcome,
+    },
+    /// The runner couldn't run the command, with the `error` of
+    /// [`RunnerError::to_json`], only `retryable` errors can succeed when the
+    /// job is submitted again
+    Failed(Value),
+    Cancelled,
+}
+
+impl JobState {
+    fn failed(error: RunnerError) -> Self {
+        JobState::Failed(error.to_json())
+    }
+
+    fn is_done(&self) -> bool {
+        !matches!(self, JobState::Queued | JobState::Running)
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct JobStatus {
+    id: u64,
+    command: &'static str,
+    #[serde(flatten)]
+    state: JobState,
+}
+
+/// Last line of a streamed job
+#[derive(Debug, Serialize)]
+struct DoneEvent {
+    event: &'static str,
+    #[serde(flatten)]
+    status: JobStatus,
+}
+
+impl Server {
+    fn status(&self, id: u64) -> Result<JobStatus, ApiError> {
+        let jobs = self.jobs.lock().unwrap();
+        let entry = jobs.get(&id).ok_or(ApiError::job_not_found(id))?;
+
+        Ok(JobStatus {
+            id,
+            command: entry.command,
+            state: entry.state.clone(),
+        })
+    }
+
+    fn set_state(&self, id: u64, state: JobState) {
+        let mut jobs = self.jobs.lock().unwrap();
+
+        if let Some(entry) = jobs.get_mut(&id) {
+            let done = state.is_done();
+            entry.state = state;
+
+            if done {
+                entry.done.send_replace(true);
+            }
+        }
+    }
+}
+
+/// Waits for a worker then runs the job, the commands block so they run on
+/// tokio's blocking threads
+async fn process_job(server: Arc<Server>, id: u64, job: Job, config: JobConfig) {
+    let permit = server
+        .workers
+        .acquire()
+        .await
+        .expect("the workers semaphore is never closed");
+
+    let cancellation = config.limits.cancellation.clone();
+
+    let state = if cancellation.is_cancelled() {
+        JobState::Cancelled
+    } else {
+        server.set_state(id, JobState::Running);
+
+        let result =
+            tokio::task::spawn_blocking(move || Handle::current().block_on(run_job(job, config)))
+                .await;
+
+        match result {
+            _ if cancellation.is_cancelled() => JobState::Cancelled,
+            Ok(Ok(output)) => JobState::Finished {
+                success: output.success(),
+                result: output.result,
+                outcome: output.outcome,
+            },
+            Ok(Err(e)) => JobState::failed(RunnerError::from(e)),
+            // The job panicked
+            Err(e) => JobState::failed(RunnerError::Internal(e.into())),
+        }
+    };
+    server.set_state(id, state);
+    drop(permit);
+
+    tokio::spawn(async move {
+        tokio::time::sleep(FINISHED_JOB_TTL).await;
+        server.jobs.lock().unwrap().remove(&id);
+    });
+}
+
+#[derive(Debug, Deserialize)]
+struct SubmitQuery {
+    #[serde(default)]
+    wait: bool,
+    #[serde(default)]
+    stream: bool,
+}
+
+async fn submit_job(
+    State(server): State<Arc<Server>>,
+    Query(query): Query<SubmitQuery>,
+    job: Result<Json<Job>, JsonRejection>,
+) -> Result<Response, ApiError> {
+    let Json(job) = job.map_err(ApiError::invalid_job)?;
+    let id = server.next_id.fetch_add(1, Ordering::SeqCst);
+    let mut config = server.config.clone();
+    config.limits.cancellation = Cancellation::default();
+    let (done, mut done_receiver) = watch::channel(false);
+
+    let stream = query.stream.then(|| {
+        let (lines, receiver) = mpsc::unbounded_channel::<String>();
+        let events = lines.clone();
+        config.events = Events::new(move |event| {
+            let _ = events.send(event.to_line());
+        });
+
+        (lines, receiver)
+    });
+
+    {
+        let mut jobs = server.jobs.lock().unwrap();
+
+        let queued = jobs
+            .values()
+            .filter(|entry| matches!(entry.state, JobState::Queued))
+            .count();
+        if queued >= server.queue_size {
+            return Err(ApiError::new(
+                StatusCode::SERVICE_UNAVAILABLE,
+                RunnerError::Internal(anyhow!("The job queue is full")),
+            ));
+        }
+
+        jobs.insert(
+            id,
+            JobEntry {
+                command: job.command(),
+                state: JobState::Queued,
+                cancellation: config.limits.cancellation.clone(),
+                done,
+            },
+        );
+    }
+
+    tokio::spawn(process_job(Arc::clone(&server), id, job, config));
+
+    if let Some((lines, receiver)) = stream {
+        let _ = lines.send(json!({ "event": "queued", "id": id }).to_string());
+
+        // The stream ends with the job's status once it's done, the events
+        // sender is dropped with the job
+        let server = Arc::clone(&server);
+        tokio::spawn(async move {
+            let _ = done_receiver.wait_for(|done| *done).await;
+            if let Ok(status) = server.status(id) {
+                let done = DoneEvent {
+                    event: "done",
+                    status,
+                };
+                let _ = lines.send(json!(done).to_string());
+            }
+        });
+
+        let body = stream::unfold(receiver, |mut receiver| async move {
+            let line = receiver.recv().await?;
+            Some((Ok::<_, Infallible>(line + "\n"), receiver))
+        });
+
+        return Ok((
+            [(header::CONTENT_TYPE, "application/x-ndjson")],
+            Body::from_stream(body),
+        )
+            .into_response());
+    }
+
+    if !query.wait {
+        return Ok((StatusCode::ACCEPTED, Json(server.status(id)?)).into_response());
+    }
+
+    // The sender lives as long as the job is kept
+    let _ = done_receiver.wait_for(|done| *done).await;
+
+    Ok((StatusCode::OK, Json(server.status(id)?)).into_response())
+}
+
+async fn job_status(
+    State(server): State<Arc<Server>>,
+    Path(id): Path<u64>,
+) -> Result<Json<JobStatus>, ApiError> {
+    Ok(Json(server.status(id)?))
+}
+
+/// A queued job is cancelled right away, a running one once its command is killed
+async fn cancel_job(
+    State(server): State<Arc<Server>>,
+    Path(id): Path<u64>,
+) -> Result<Json<JobStatus>, ApiError> {
+    let queued = {
+        let jobs = server.jobs.lock().unwrap();
+        let entry = jobs.get(&id).ok_or(ApiError::job_not_found(id))?;
+
+        if !entry.state.is_done() {
+            entry.cancellation.cancel();
+        }
+
+        matches!(entry.state, JobState::Queued)
+    };
+
+    if queued {
+        server.set_state(id, JobState::Cancelled);
+    }
+
+    Ok(Json(server.status(id)?))
+}
+
+async fn server_status(State(server): State<Arc<Server>>) -> Json<Value> {
+    let jobs = server.jobs.lock().unwrap();
+    let count = |state: fn(&JobState) -> bool| jobs.values().filter(|e| state(&e.state)).count();
+
+    Json(json!({
+        "workers": server.n_workers,
+        "running": count(|state| matches!(state, JobState::Running)),
+        "queued": count(|state| matches!(state, JobState::Queued)),
+        "sandbox": server.config.limits.sandbox,
+    }))
+}
+
+/// An error response, its body is the error's JSON like a job that failed
+struct ApiError(StatusCode, RunnerError);
+
+impl ApiError {
+    fn new(status: StatusCode, error: RunnerError) -> Self {
+        ApiError(status, error)
+    }
+
+    fn job_not_found(id: u64) -> Self {
+        ApiError::new(
+            StatusCode::NOT_FOUND,
+            RunnerError::InvalidJob(anyhow!("No job with id {}", id)),
+        )
+    }
+
+    /// A body that isn't a job
+    fn invalid_job(rejection: JsonRejection) -> Self {
+        ApiError::new(
+            StatusCode::UNPROCESSABLE_ENTITY,
+            RunnerError::InvalidJob(anyhow!(rejection.body_text())),
+        )
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        (self.0, Json(self.1.to_json())).into_response()
+    }
+}
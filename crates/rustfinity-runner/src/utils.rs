@@ -1,33 +1,30 @@
 use std::{
     fs::{self, OpenOptions},
-    io::Write,
+    io::{Read, Write},
     path::Path,
 };
 
 use base64::{prelude::BASE64_STANDARD, Engine};
-use duct::cmd;
+
+use crate::process::LimitExceeded;
 
 pub fn to_utf8(base64: &str) -> anyhow::Result<String> {
-    let utf8 = BASE64_STANDARD.decode(base64)?;
-    Ok(String::from_utf8(utf8)?)
+    Ok(String::from_utf8(from_base64(base64)?)?)
+}
+
+pub fn from_base64(base64: &str) -> anyhow::Result<Vec<u8>> {
+    Ok(BASE64_STANDARD.decode(base64)?)
 }
 
-pub async fn run_command_and_merge_output(
-    command: &str,
-    args: &[&str],
-    cwd: Option<&str>,
-) -> anyhow::Result<String> {
-    let cwd = cwd.unwrap_or(".");
-
-    let output = cmd!(command, args.join(" "))
-        .stderr_to_stdout()
-        .stdout_capture()
-        .dir(cwd)
-        // don't care about exit code
-        .unchecked()
-        .run()?;
-
-    Ok(String::from_utf8(output.stdout)?)
+/// Reads a file, or stdin when `path` is `-`
+pub fn read_input(path: &str) -> std::io::Result<Vec<u8>> {
+    if path == "-" {
+        let mut input = Vec::new();
+        std::io::stdin().read_to_end(&mut input)?;
+        return Ok(input);
+    }
+
+    fs::read(path)
 }
 
 pub fn write_file(path: &Path, content: &str) -> std::io::Result<()> {
@@ -42,3 +39,34 @@ pub fn write_file(path: &Path, content: &str) -> std::io::Result<()> {
     file.write_all(content.as_bytes())?;
     Ok(())
 }
+
+/// Drops the `Executable` lines printed by `cargo test --no-run`
+pub fn without_executable_lines(output: &str) -> String {
+    output
+        .lines()
+        .filter(|line| !line.trim_start().starts_with("Executable "))
+        .map(|line| format!("{}\n", line))
+        .collect()
+}
+
+/// Drops the lines cargo prints while waiting for a lock held by a concurrent run
+pub fn without_lock_lines(output: &str) -> String {
+    output
+        .lines()
+        .filter(|line| {
+            !line
+                .trim_start()
+                .starts_with("Blocking waiting for file lock")
+        })
+        .map(|line| format!("{}\n", line))
+        .collect()
+}
+
+/// Appends the limit that was hit to the output, on its own line
+pub fn push_limit_exceeded(output: &mut String, limit_exceeded: &LimitExceeded) {
+    if !output.is_empty() && !output.ends_with('\n') {
+        output.push('\n');
+    }
+    output.push_str(&limit_exceeded.to_string());
+    output.push('\n');
+}
@@ -0,0 +1,287 @@
+use serde::Serialize;
+
+use crate::diagnostics::Diagnostic;
+use crate::error::Outcome;
+use crate::process::LimitExceeded;
+
+/// Structured result of a `test` run, returned with `--format json`
+#[derive(Debug, Serialize)]
+pub struct Verdict {
+    pub compile: CompileStatus,
+    /// Compiler output, useful to show the errors when compilation fails
+    pub compile_output: String,
+    pub diagnostics: Vec<Diagnostic>,
+    pub tests: Vec<TestResult>,
+    pub totals: Totals,
+    /// Zero when the code didn't compile or a limit was hit
+    pub score: Score,
+    pub benchmark: Option<Benchmark>,
+    /// The benchmark against the reference solution, when the job has one
+    pub comparison: Option<Comparison>,
+    /// How each test binary exited, one dying partway through fails the run
+    pub test_exits: Vec<TestExit>,
+    /// Set when the compilation, the tests or the benchmarks were killed
+    pub limit_exceeded: Option<LimitExceeded>,
+    /// Whether the verdict is the one of an identical earlier job, from the
+    /// result cache
+    pub cached: bool,
+}
+
+impl Verdict {
+    pub fn outcome(&self) -> Outcome {
+        let comparison_failed = self
+            .comparison
+            .as_ref()
+            .is_some_and(|comparison| comparison.passed == Some(false));
+
+        match &self.limit_exceeded {
+            Some(limit_exceeded) => Outcome::from_limit(limit_exceeded),
+            None if self.compile == CompileStatus::Failed => Outcome::CompileError,
+            None if self.totals.failed > 0
+                || comparison_failed
+                || self.test_exits.iter().any(TestExit::crashed) =>
+            {
+                Outcome::Failed
+            }
+            None => Outcome::Passed,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CompileStatus {
+    Success,
+    Failed,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct TestResult {
+    pub name: String,
+    pub status: TestStatus,
+    /// Panic message of a failed test
+    pub message: Option<String>,
+    /// Frames of the panic in the submitted files, from where it panicked
+    pub backtrace: Vec<BacktraceFrame>,
+    pub duration_ms: Option<f64>,
+    /// Whether the test is from the hidden suite
+    pub hidden: bool,
+}
+
+/// The exit of a test binary
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct TestExit {
+    /// Name of the test target, e.g. `tests`
+    pub target: String,
+    pub exit_code: Option<i32>,
+    pub signal: Option<i32>,
+    /// Whether libtest finished the suite, it doesn't when the binary crashes
+    /// or the code exits the process
+    pub completed: bool,
+}
+
+impl TestExit {
+    /// libtest exits with 101 when a test failed, anything else but a
+    /// success is the binary dying
+    pub fn crashed(&self) -> bool {
+        !self.completed || !matches!(self.exit_code, Some(0 | 101))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct BacktraceFrame {
+    pub function: String,
+    /// Path relative to the crate, e.g. `src/lib.rs`
+    pub file: String,
+    pub line: u32,
+    pub column: u32,
+}
+
+impl TestResult {
+    /// Only the name and status, for the tests of the hidden suite
+    pub fn redacted(self) -> Self {
+        Self {
+            message: None,
+            backtrace: Vec::new(),
+            duration_ms: None,
+            hidden: true,
+            ..self
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TestStatus {
+    Passed,
+    Failed,
+    Ignored,
+}
+
+#[derive(Debug, Default, PartialEq, Eq, Serialize)]
+pub struct Totals {
+    pub passed: usize,
+    pub failed: usize,
+    pub ignored: usize,
+}
+
+impl Totals {
+    pub fn from_tests(tests: &[TestResult]) -> Self {
+        let mut totals = Totals::default();
+
+        for test in tests {
+            match test.status {
+                TestStatus::Passed => totals.passed += 1,
+                TestStatus::Failed => totals.failed += 1,
+                TestStatus::Ignored => totals.ignored += 1,
+            }
+        }
+
+        totals
+    }
+}
+
+/// Out of 100, from the weights of the tests that passed
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Score {
+    pub score: u32,
+    /// Weights of the passed tests
+    pub earned: u64,
+    /// Weights of the tests that ran and of the listed tests that didn't,
+    /// ignored ones aren't counted
+    pub total: u64,
+    /// Concept groups of the scoring manifest with a test that didn't pass
+    pub missed_groups: Vec<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Benchmark {
+    /// Wall time of `cargo test --no-run` in milliseconds
+    pub compile_time_ms: f64,
+    /// Run times of the tests binary, the warm-up runs aren't included
+    pub run_time: TimeStats,
+    pub warmup_runs: usize,
+    /// Run times of each test reported by libtest
+    pub tests: Vec<TestTiming>,
+    /// Times of the challenge's benchmark function, when it has one
+    pub function: Option<FunctionBenchmark>,
+    /// Highest peak resident set size of the runs
+    pub peak_rss_bytes: u64,
+    /// Measured by the heap profiler, `None` when there's none or it couldn't
+    /// measure the tests
+    pub heap: Option<HeapUsage>,
+}
+
+/// Statistics of timings in milliseconds
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct TimeStats {
+    pub runs: usize,
+    pub min_ms: f64,
+    pub median_ms: f64,
+    pub p95_ms: f64,
+    pub mean_ms: f64,
+    pub stddev_ms: f64,
+}
+
+impl TimeStats {
+    /// All zeros when there are no samples
+    pub fn from_samples(samples: &[f64]) -> Self {
+        let mut sorted = samples.to_vec();
+        sorted.sort_by(f64::total_cmp);
+
+        // Nearest-rank percentile
+        let percentile = |p: f64| {
+            let rank = (p / 100.0 * sorted.len() as f64).ceil() as usize;
+            sorted
+                .get(rank.clamp(1, sorted.len().max(1)) - 1)
+                .copied()
+                .unwrap_or_default()
+        };
+
+        let runs = sorted.len();
+        let mean_ms = if runs == 0 {
+            0.0
+        } else {
+            sorted.iter().sum::<f64>() / runs as f64
+        };
+        let variance = if runs < 2 {
+            0.0
+        } else {
+            sorted.iter().map(|x| (x - mean_ms).powi(2)).sum::<f64>() / (runs - 1) as f64
+        };
+
+        let median_ms = match runs {
+            0 => 0.0,
+            n if n % 2 == 0 => (sorted[n / 2 - 1] + sorted[n / 2]) / 2.0,
+            n => sorted[n / 2],
+        };
+
+        Self {
+            runs,
+            min_ms: sorted.first().copied().unwrap_or_default(),
+            median_ms,
+            p95_ms: percentile(95.0),
+            mean_ms,
+            stddev_ms: variance.sqrt(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct TestTiming {
+    pub name: String,
+    pub time: TimeStats,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct FunctionBenchmark {
+    /// The benchmarked call, e.g. `fibonacci(30)`
+    pub call: String,
+    /// Times of each call after the warm-up calls
+    pub time: TimeStats,
+}
+
+/// The submission's benchmark relative to the reference solution's, ratios
+/// above 1 are slower or use more memory
+#[derive(Debug, Serialize)]
+pub struct Comparison {
+    pub reference: Benchmark,
+    /// Of the median times of the benchmark function when there's one,
+    /// otherwise of the tests binary
+    pub speed_ratio: Option<f64>,
+    /// Of the peak heap bytes, or the peak RSS when the heap isn't measured
+    pub memory_ratio: Option<f64>,
+    /// Whether the submission is within the thresholds, `None` without any
+    pub passed: Option<bool>,
+    /// The thresholds the submission went over
+    pub failures: Vec<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct HeapUsage {
+    pub peak_bytes: u64,
+    /// Calls to the allocation functions
+    pub allocations: u64,
+    /// Bytes allocated in total, heaptrack doesn't report it
+    pub allocated_bytes: Option<u64>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_time_stats_from_samples() {
+        let stats = TimeStats::from_samples(&[4.0, 1.0, 3.0, 2.0, 100.0]);
+
+        assert_eq!(stats.runs, 5);
+        assert_eq!(stats.min_ms, 1.0);
+        assert_eq!(stats.median_ms, 3.0);
+        assert_eq!(stats.p95_ms, 100.0);
+        assert_eq!(stats.mean_ms, 22.0);
+        assert!((stats.stddev_ms - 43.6177).abs() < 0.001);
+
+        assert_eq!(TimeStats::from_samples(&[]).median_ms, 0.0);
+        assert_eq!(TimeStats::from_samples(&[2.0, 3.0]).median_ms, 2.5);
+    }
+}
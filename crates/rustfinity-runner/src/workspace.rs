@@ -0,0 +1,316 @@
+use std::{
+    fs::{self, File},
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use anyhow::Context;
+use toml::{Table, Value};
+
+use crate::{
+    constants::PLAYGROUND_DIR,
+    edition::Edition,
+    files::{check_path, Files},
+    utils::write_file,
+};
+
+/// A scratch crate used by a single run.
+///
+/// Scratch crates live in numbered slots next to the playground project, so
+/// the relative path dependencies of the challenges (`../../crates/syntest`)
+/// resolve the same way. A slot is locked while it's in use and emptied when
+/// it's taken and when it's released.
+///
+/// Every slot has its own target directory. Cargo names the artifacts of the
+/// crate the same way in every slot, so slots can't share one without running
+/// each other's binaries. The first time a slot is used its target directory
+/// is seeded with a copy of the playground's, which has the dependencies
+/// precompiled, and it stays warm for the next runs in that slot.
+pub struct Workspace {
+    path: PathBuf,
+    /// Files written to the crate, relative to its root
+    written: Vec<String>,
+    _lock: File,
+}
+
+impl Workspace {
+    pub fn new() -> anyhow::Result<Self> {
+        let project = PathBuf::from(project_path());
+        let parent = project
+            .parent()
+            .context("The playground project has no parent directory")?;
+
+        for slot in 0.. {
+            let lock = File::create(parent.join(format!(".run-{}.lock", slot)))
+                .context("Failed to create the workspace lock")?;
+
+            if lock.try_lock().is_err() {
+                continue;
+            }
+
+            let workspace = Self {
+                path: parent.join(format!(".run-{}", slot)),
+                written: Vec::new(),
+                _lock: lock,
+            };
+            workspace.clear()?;
+            workspace.seed_target_dir(&project)?;
+
+            return Ok(workspace);
+        }
+
+        unreachable!("there is always a free slot")
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Files written to the crate, the diagnostics are mapped onto these
+    pub fn files(&self) -> Vec<&str> {
+        self.written.iter().map(String::as_str).collect()
+    }
+
+    /// Writes a file relative to the crate root
+    pub fn write(&mut self, relative_path: &str, content: &str) -> std::io::Result<()> {
+        write_file(&self.path.join(relative_path), content)?;
+        self.written.push(relative_path.to_string());
+
+        Ok(())
+    }
+
+    /// Writes the extra files of a submission, after the ones the command
+    /// writes itself so they can't be replaced
+    pub fn write_files(&mut self, files: &Files) -> anyhow::Result<()> {
+        for (path, content) in files {
+            check_path(path, &self.files())?;
+            self.write(path, content)?;
+        }
+
+        Ok(())
+    }
+
+    /// Writes the manifest, made standalone so cargo doesn't look for a
+    /// parent workspace, with the lockfile of the playground's workspace
+    pub fn write_manifest(&mut self, cargo_toml: &str) -> anyhow::Result<()> {
+        self.write("Cargo.toml", &standalone_manifest(cargo_toml))?;
+
+        let project = PathBuf::from(project_path());
+        let lockfile = [project.join("../../Cargo.lock"), project.join("Cargo.lock")]
+            .into_iter()
+            .find(|path| path.exists());
+
+        if let Some(lockfile) = lockfile {
+            fs::copy(lockfile, self.path.join("Cargo.lock"))
+                .context("Failed to copy Cargo.lock")?;
+        }
+
+        Ok(())
+    }
+
+    /// Copies the manifest of the playground project, with another edition
+    /// if one is given
+    pub fn write_playground_manifest(&mut self, edition: Option<Edition>) -> anyhow::Result<()> {
+        let cargo_toml = fs::read_to_string(Path::new(&project_path()).join("Cargo.toml"))
+            .context("Failed to read the playground Cargo.toml")?;
+
+        let Some(edition) = edition else {
+            return self.write_manifest(&cargo_toml);
+        };
+
+        let mut manifest = cargo_toml.parse::<Table>()?;
+        manifest
+            .get_mut("package")
+            .and_then(Value::as_table_mut)
+            .context("The playground Cargo.toml has no [package]")?
+            .insert("edition".to_string(), Value::from(edition.as_str()));
+
+        self.write_manifest(&manifest.to_string())
+    }
+
+    /// A cargo command running in this crate
+    pub fn cargo(&self) -> Command {
+        let mut command = Command::new("cargo");
+        command
+            .current_dir(&self.path)
+            .env("CARGO_TARGET_DIR", self.path.join("target"));
+        command
+    }
+
+    /// Copies the `debug` and `release` directories of the playground's
+    /// workspace target into the slot's target directory, if the slot doesn't
+    /// have one yet. Incremental caches are left out, they only exist for
+    /// local crates
+    fn seed_target_dir(&self, project: &Path) -> anyhow::Result<()> {
+        let target_dir = self.path.join("target");
+
+        if target_dir.exists() {
+            return Ok(());
+        }
+
+        let warm_target_dir = match std::env::var("CARGO_TARGET_DIR") {
+            Ok(target_dir) => PathBuf::from(target_dir),
+            Err(_) => project.join("../../target"),
+        };
+
+        let seeding = self.path.join("target.seeding");
+        fs::create_dir_all(&seeding)?;
+        for profile in ["debug", "release"] {
+            if warm_target_dir.join(profile).exists() {
+                copy_dir(&warm_target_dir.join(profile), &seeding.join(profile))
+                    .context("Failed to seed the target directory")?;
+            }
+        }
+        fs::rename(seeding, target_dir)?;
+
+        Ok(())
+    }
+
+    /// Removes what cargo built for the crate's `targets` and their
+    /// fingerprints, e.g. the hidden tests once they ran. Code that's only
+    /// for one job doesn't stay in the target directory the slot keeps
+    pub fn remove_artifacts(&self, targets: &[&str]) -> std::io::Result<()> {
+        // `hidden-1a2b`, `hidden-1a2b.d`, `libfibonacci-1a2b.rlib`
+        let is_artifact = |name: &str| {
+            targets.iter().any(|target| {
+                name.strip_prefix("lib")
+                    .into_iter()
+                    .chain([name])
+                    .filter_map(|name| name.strip_prefix(target))
+                    .any(|rest| rest.is_empty() || rest.starts_with(['-', '.']))
+            })
+        };
+
+        for profile in ["debug", "release"] {
+            let profile_dir = self.path.join("target").join(profile);
+
+            for dir in ["deps", "examples", "incremental"] {
+                remove_entries(&profile_dir.join(dir), |entry| {
+                    Ok(is_artifact(&entry.file_name().to_string_lossy()))
+                })?;
+            }
+
+            // Named after the package, with a file named after the target,
+            // e.g. `test-integration-test-hidden`
+            remove_entries(&profile_dir.join(".fingerprint"), |entry| {
+                if !entry.file_type()?.is_dir() {
+                    return Ok(false);
+                }
+                for file in fs::read_dir(entry.path())? {
+                    let name = file?.file_name();
+                    let name = name.to_string_lossy();
+                    if targets
+                        .iter()
+                        .any(|target| name.ends_with(&format!("-{}", target)))
+                    {
+                        return Ok(true);
+                    }
+                }
+                Ok(false)
+            })?;
+        }
+
+        Ok(())
+    }
+
+    /// Removes everything but the crate's target directory
+    fn clear(&self) -> anyhow::Result<()> {
+        if !self.path.exists() {
+            fs::create_dir_all(&self.path).context("Failed to create the workspace")?;
+            return Ok(());
+        }
+
+        for entry in fs::read_dir(&self.path)? {
+            let entry = entry?;
+
+            if entry.file_name() == "target" {
+                continue;
+            }
+
+            if entry.file_type()?.is_dir() {
+                fs::remove_dir_all(entry.path())?;
+            } else {
+                fs::remove_file(entry.path())?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl Drop for Workspace {
+    fn drop(&mut self) {
+        let _ = self.clear();
+    }
+}
+
+/// The project the scratch crates are modeled after
+pub fn project_path() -> String {
+    std::env::var("PROJECT_PATH").unwrap_or(PLAYGROUND_DIR.to_string())
+}
+
+/// Removes the files and directories of `dir` that match, if it exists
+fn remove_entries(
+    dir: &Path,
+    matches: impl Fn(&fs::DirEntry) -> std::io::Result<bool>,
+) -> std::io::Result<()> {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e),
+    };
+
+    for entry in entries {
+        let entry = entry?;
+        if !matches(&entry)? {
+            continue;
+        }
+
+        if entry.file_type()?.is_dir() {
+            fs::remove_dir_all(entry.path())?;
+        } else {
+            fs::remove_file(entry.path())?;
+        }
+    }
+
+    Ok(())
+}
+
+fn copy_dir(from: &Path, to: &Path) -> std::io::Result<()> {
+    fs::create_dir_all(to)?;
+
+    for entry in fs::read_dir(from)? {
+        let entry = entry?;
+        let file_type = entry.file_type()?;
+        let to = to.join(entry.file_name());
+
+        if file_type.is_dir() && entry.file_name() == "incremental" {
+            continue;
+        } else if file_type.is_dir() {
+            copy_dir(&entry.path(), &to)?;
+        } else if file_type.is_file() {
+            fs::copy(entry.path(), &to)?;
+            // Cargo compares modification times to tell what's fresh
+            File::options()
+                .write(true)
+                .open(&to)?
+                .set_modified(entry.metadata()?.modified()?)?;
+        }
+    }
+
+    Ok(())
+}
+
+fn standalone_manifest(cargo_toml: &str) -> String {
+    if cargo_toml.lines().any(|line| line.trim() == "[workspace]") {
+        return cargo_toml.to_string();
+    }
+
+    let mut cargo_toml = cargo_toml.to_string();
+    if !cargo_toml.ends_with('\n') {
+        cargo_toml.push('\n');
+    }
+    cargo_toml.push_str("\n[workspace]\n");
+
+    cargo_toml
+}
@@ -1,6 +1,7 @@
 #!/usr/bin/env python3
 import argparse
 import base64
+import json
 import subprocess
 import sys
 from pathlib import Path
@@ -54,6 +55,15 @@ def main():
             print(f"Error: Docker build failed", file=sys.stderr)
             sys.exit(1)
     
+    # The job goes through stdin, large test files don't fit in the arguments
+    job = {
+        'command': 'test',
+        'code': code_b64,
+        'tests': tests_b64,
+        'cargo_toml': cargo_toml_b64,
+        'n_tests': args.n_tests,
+    }
+
     docker_cmd = [
         'docker', 'run', '-i',
         '--rm',
@@ -61,11 +71,10 @@ def main():
         '--cpus=1',
         '-m=500m',
         args.image,
-        '/bin/bash', '-c',
-        f"/app/rustfinity-runner test --code '{code_b64}' --tests '{tests_b64}' --cargo-toml '{cargo_toml_b64}' --n-tests {args.n_tests}"
+        '/app/rustfinity-runner', 'run', '--job', '-'
     ]
     
-    subprocess.run(docker_cmd)
+    subprocess.run(docker_cmd, input=json.dumps(job), text=True)
 
 if __name__ == '__main__':
     main()